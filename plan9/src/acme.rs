@@ -1,5 +1,5 @@
 use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 use anyhow::{bail, Result};
 use lazy_static::lazy_static;
@@ -8,7 +8,8 @@ use nine::p2000::OpenMode;
 use crate::dial;
 use crate::{fid::Fid, fsys::Fsys};
 lazy_static! {
-    pub static ref FSYS: Mutex<Fsys> = Mutex::new(dial::mount_service("acme").unwrap());
+    pub static ref FSYS: Arc<Mutex<Fsys>> =
+        Arc::new(Mutex::new(dial::mount_service("acme").unwrap()));
 }
 
 #[derive(Debug)]
@@ -19,20 +20,23 @@ pub struct WinInfo {
 
 impl WinInfo {
     pub fn windows() -> Result<Vec<WinInfo>> {
-        let index = FSYS.lock().unwrap().open("index", OpenMode::READ)?;
+        WinInfo::windows_in(&FSYS)
+    }
+    // windows_in lists the windows of the acme mounted at fsys.
+    pub fn windows_in(fsys: &Mutex<Fsys>) -> Result<Vec<WinInfo>> {
+        let index = fsys.lock().unwrap().open("index", OpenMode::READ)?;
         let r = BufReader::new(index);
         let mut ws = Vec::new();
         for line in r.lines() {
-            if let Ok(line) = line {
-                let sp: Vec<&str> = line.split_whitespace().collect();
-                if sp.len() < 6 {
-                    continue;
-                }
-                ws.push(WinInfo {
-                    id: sp[0].parse()?,
-                    name: sp[5].to_string(),
-                });
+            let line = line?;
+            let sp: Vec<&str> = line.split_whitespace().collect();
+            if sp.len() < 6 {
+                continue;
             }
+            ws.push(WinInfo {
+                id: sp[0].parse()?,
+                name: sp[5].to_string(),
+            });
         }
         Ok(ws)
     }
@@ -46,13 +50,45 @@ pub struct LogReader {
 #[derive(Debug)]
 pub struct LogEvent {
     pub id: usize,
-    pub op: String,
+    pub op: LogOp,
     pub name: String,
 }
 
+// LogOp is a window operation reported by acme's log file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LogOp {
+    New,
+    Zerox,
+    Get,
+    Put,
+    Del,
+    Focus,
+    // Other is an op this doesn't know, from a newer acme.
+    Other(String),
+}
+
+impl std::str::FromStr for LogOp {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<LogOp> {
+        Ok(match s {
+            "new" => LogOp::New,
+            "zerox" => LogOp::Zerox,
+            "get" => LogOp::Get,
+            "put" => LogOp::Put,
+            "del" => LogOp::Del,
+            "focus" => LogOp::Focus,
+            _ => LogOp::Other(s.to_string()),
+        })
+    }
+}
+
 impl LogReader {
     pub fn new() -> Result<LogReader> {
-        let log = FSYS.lock().unwrap().open("log", OpenMode::READ)?;
+        LogReader::new_in(&FSYS)
+    }
+    pub fn new_in(fsys: &Mutex<Fsys>) -> Result<LogReader> {
+        let log = fsys.lock().unwrap().open("log", OpenMode::READ)?;
         Ok(LogReader {
             f: log,
             buf: [0; 8192],
@@ -66,7 +102,7 @@ impl LogReader {
             bail!("malformed log event");
         }
         let id = sp[0].parse()?;
-        let op = sp[1].to_string();
+        let op = sp[1].parse()?;
         let name = sp[2].trim().to_string();
         Ok(LogEvent { id, op, name })
     }
@@ -74,26 +110,115 @@ impl LogReader {
 
 pub struct Win {
     id: usize,
+    fsys: Arc<Mutex<Fsys>>,
     ctl: Fid,
     body: Fid,
     addr: Fid,
     data: Fid,
     tag: Fid,
+    // The remaining files are opened on first use: opening errors creates a
+    // +Errors window and editout can only be opened during an Edit command.
+    xdata: Option<Fid>,
+    errors: Option<Fid>,
+    rdsel: Option<Fid>,
+    wrsel: Option<Fid>,
+    editout: Option<Fid>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum File {
     Ctl,
     Body,
     Addr,
     Data,
     Tag,
+    Xdata,
+    Errors,
+    Rdsel,
+    Wrsel,
+    Editout,
+}
+
+impl File {
+    fn name(&self) -> &'static str {
+        match self {
+            File::Ctl => "ctl",
+            File::Body => "body",
+            File::Addr => "addr",
+            File::Data => "data",
+            File::Tag => "tag",
+            File::Xdata => "xdata",
+            File::Errors => "errors",
+            File::Rdsel => "rdsel",
+            File::Wrsel => "wrsel",
+            File::Editout => "editout",
+        }
+    }
+    fn mode(&self) -> OpenMode {
+        match self {
+            File::Rdsel => OpenMode::READ,
+            File::Errors | File::Wrsel | File::Editout => OpenMode::WRITE,
+            _ => OpenMode::RDWR,
+        }
+    }
+}
+
+// CtlInfo is the parsed contents of a window's ctl file.
+#[derive(Debug, PartialEq)]
+pub struct CtlInfo {
+    pub id: usize,
+    pub tag_len: usize,
+    pub body_len: usize,
+    pub isdir: bool,
+    pub dirty: bool,
+    // The following are only reported by windows that have a font.
+    pub width: Option<usize>,
+    pub font: Option<String>,
+    pub tabwidth: Option<usize>,
+}
+
+impl std::str::FromStr for CtlInfo {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<CtlInfo> {
+        let sp: Vec<&str> = s.split_whitespace().collect();
+        if sp.len() < 5 {
+            bail!("short read from acme ctl");
+        }
+        let (width, font, tabwidth) = if sp.len() >= 8 {
+            // The font name is quoted if it contains spaces.
+            let font = sp[6..sp.len() - 1].join(" ");
+            (
+                Some(sp[5].parse()?),
+                Some(font.trim_matches('\'').replace("''", "'")),
+                Some(sp[sp.len() - 1].parse()?),
+            )
+        } else {
+            (None, None, None)
+        };
+        Ok(CtlInfo {
+            id: sp[0].parse()?,
+            tag_len: sp[1].parse()?,
+            body_len: sp[2].parse()?,
+            isdir: sp[3] == "1",
+            dirty: sp[4] == "1",
+            width,
+            font,
+            tabwidth,
+        })
+    }
 }
 
-pub struct WinEvents {
-    event: Fid,
+pub struct WinEvents<F = Fid> {
+    event: BufReader<F>,
 }
 
-impl WinEvents {
+impl<F: Read + Write> WinEvents<F> {
+    pub fn new(event: F) -> WinEvents<F> {
+        WinEvents {
+            event: BufReader::new(event),
+        }
+    }
     pub fn read_event(&mut self) -> Result<Event> {
         let mut e = self.get_event()?;
 
@@ -118,18 +243,29 @@ impl WinEvents {
 
         Ok(e)
     }
+    // get_ch reads one UTF-8 encoded rune.
     fn get_ch(&mut self) -> Result<char> {
-        let mut buf = [0; 1];
-        // TODO: figure out how to use a BufReader here to avoid a bunch of single reads.
-        self.event.read_exact(&mut buf)?;
-        Ok(buf[0] as char)
+        let mut buf = [0; 4];
+        self.event.read_exact(&mut buf[..1])?;
+        let n = match buf[0] {
+            0x00..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return Ok(std::char::REPLACEMENT_CHARACTER),
+        };
+        self.event.read_exact(&mut buf[1..n])?;
+        Ok(match std::str::from_utf8(&buf[..n]) {
+            Ok(s) => s.chars().next().unwrap(),
+            Err(_) => std::char::REPLACEMENT_CHARACTER,
+        })
     }
     fn get_en(&mut self) -> Result<u32> {
         let mut c: char;
         let mut n: u32 = 0;
         loop {
             c = self.get_ch()?;
-            if c < '0' || c > '9' {
+            if !c.is_ascii_digit() {
                 break;
             }
             n = n * 10 + c.to_digit(10).unwrap();
@@ -174,73 +310,105 @@ impl WinEvents {
     }
     pub fn write_event(&mut self, ev: Event) -> Result<()> {
         let s = format!("{}{}{} {} \n", ev.c1, ev.c2, ev.q0, ev.q1);
-        self.event.write(s.as_bytes())?;
+        self.event.get_mut().write_all(s.as_bytes())?;
         Ok(())
     }
 }
 
 impl Win {
     pub fn new() -> Result<Win> {
-        let mut fsys = FSYS.lock().unwrap();
-        let mut fid = fsys.open("new/ctl", OpenMode::RDWR)?;
+        Win::new_in(&FSYS)
+    }
+    // new_in creates a window in the acme mounted at fsys.
+    pub fn new_in(fsys: &Arc<Mutex<Fsys>>) -> Result<Win> {
+        let mut fid = fsys.lock().unwrap().open("new/ctl", OpenMode::RDWR)?;
         let mut buf = [0; 100];
         let sz = fid.read(&mut buf)?;
         let data = String::from_utf8(buf[0..sz].to_vec())?;
         let sp: Vec<&str> = data.split_whitespace().collect();
-        if sp.len() == 0 {
+        if sp.is_empty() {
             bail!("short read from acme/new/ctl");
         }
         let id = sp[0].parse()?;
-        Win::open(&mut fsys, id, fid)
+        Win::open_ctl(fsys, id, fid)
     }
     // open connects to the existing window with the given id.
-    pub fn open(fsys: &mut Fsys, id: usize, ctl: Fid) -> Result<Win> {
-        let body = fsys.open(format!("{}/body", id).as_str(), OpenMode::RDWR)?;
-        let addr = fsys.open(format!("{}/addr", id).as_str(), OpenMode::RDWR)?;
-        let data = fsys.open(format!("{}/data", id).as_str(), OpenMode::RDWR)?;
-        let tag = fsys.open(format!("{}/tag", id).as_str(), OpenMode::RDWR)?;
+    pub fn open(fsys: &Arc<Mutex<Fsys>>, id: usize) -> Result<Win> {
+        let ctl = fsys
+            .lock()
+            .unwrap()
+            .open(&format!("{}/ctl", id), OpenMode::RDWR)?;
+        Win::open_ctl(fsys, id, ctl)
+    }
+    fn open_ctl(fsys: &Arc<Mutex<Fsys>>, id: usize, ctl: Fid) -> Result<Win> {
+        let mut f = fsys.lock().unwrap();
+        let body = f.open(format!("{}/body", id).as_str(), OpenMode::RDWR)?;
+        let addr = f.open(format!("{}/addr", id).as_str(), OpenMode::RDWR)?;
+        let data = f.open(format!("{}/data", id).as_str(), OpenMode::RDWR)?;
+        let tag = f.open(format!("{}/tag", id).as_str(), OpenMode::RDWR)?;
         Ok(Win {
             id,
+            fsys: Arc::clone(fsys),
             ctl,
             body,
             addr,
             data,
             tag,
+            xdata: None,
+            errors: None,
+            rdsel: None,
+            wrsel: None,
+            editout: None,
         })
     }
     pub fn events(&mut self) -> Result<WinEvents> {
-        let event = FSYS
+        let event = self
+            .fsys
             .lock()
             .unwrap()
             .open(format!("{}/event", self.id).as_str(), OpenMode::RDWR)?;
-        Ok(WinEvents { event })
+        Ok(WinEvents::new(event))
     }
     pub fn id(&self) -> usize {
         self.id
     }
     pub fn write(&mut self, file: File, data: &str) -> Result<()> {
-        let f = self.fid(file);
-        f.write(data.as_bytes())?;
+        let f = self.fid(file)?;
+        // Unlike write_all, Fid::write sends empty writes, which acme uses
+        // to delete the addressed text.
+        if f.write(data.as_bytes())? != data.len() {
+            bail!("short write to acme {:?}", file);
+        }
         Ok(())
     }
-    fn fid(&mut self, file: File) -> &mut Fid {
-        match file {
-            File::Ctl => &mut self.ctl,
-            File::Body => &mut self.body,
-            File::Addr => &mut self.addr,
-            File::Data => &mut self.data,
-            File::Tag => &mut self.tag,
+    fn fid(&mut self, file: File) -> Result<&mut Fid> {
+        let lazy = match file {
+            File::Ctl => return Ok(&mut self.ctl),
+            File::Body => return Ok(&mut self.body),
+            File::Addr => return Ok(&mut self.addr),
+            File::Data => return Ok(&mut self.data),
+            File::Tag => return Ok(&mut self.tag),
+            File::Xdata => &mut self.xdata,
+            File::Errors => &mut self.errors,
+            File::Rdsel => &mut self.rdsel,
+            File::Wrsel => &mut self.wrsel,
+            File::Editout => &mut self.editout,
+        };
+        if lazy.is_none() {
+            let name = format!("{}/{}", self.id, file.name());
+            *lazy = Some(self.fsys.lock().unwrap().open(&name, file.mode())?);
         }
+        Ok(lazy.as_mut().unwrap())
     }
     pub fn ctl(&mut self, data: &str) -> Result<()> {
         self.write(File::Ctl, &format!("{}\n", data))
     }
     pub fn addr(&mut self, data: &str) -> Result<()> {
-        self.write(File::Addr, &format!("{}", data))
+        self.write(File::Addr, data)
     }
     pub fn clear(&mut self) -> Result<()> {
-        self.write(File::Addr, &format!(","))?;
-        self.write(File::Data, &format!(""))?;
+        self.write(File::Addr, ",")?;
+        self.write(File::Data, "")?;
         Ok(())
     }
     pub fn name(&mut self, name: &str) -> Result<()> {
@@ -250,9 +418,32 @@ impl Win {
         let cmd = if sure { "delete" } else { "del" };
         self.ctl(cmd)
     }
+    // err writes msg to the window's +Errors window.
+    pub fn err(&mut self, msg: &str) -> Result<()> {
+        self.write(File::Errors, &format!("{}\n", msg))
+    }
+    // info reads and parses the window's ctl file.
+    pub fn info(&mut self) -> Result<CtlInfo> {
+        let mut buf = String::new();
+        self.read(File::Ctl)?.read_to_string(&mut buf)?;
+        buf.parse()
+    }
+    // read_range returns the body text between rune offsets q0 and q1.
+    pub fn read_range(&mut self, q0: u32, q1: u32) -> Result<String> {
+        self.addr(&format!("#{},#{}", q0, q1))?;
+        let mut buf = String::new();
+        self.read(File::Xdata)?.read_to_string(&mut buf)?;
+        Ok(buf)
+    }
+    // replace_range replaces the body text between rune offsets q0 and q1
+    // with text.
+    pub fn replace_range(&mut self, q0: u32, q1: u32, text: &str) -> Result<()> {
+        self.addr(&format!("#{},#{}", q0, q1))?;
+        self.write(File::Data, text)
+    }
     pub fn read_addr(&mut self) -> Result<(u32, u32)> {
         let mut buf: [u8; 40] = [0; 40];
-        let f = self.fid(File::Addr);
+        let f = self.fid(File::Addr)?;
         f.seek(SeekFrom::Start(0))?;
         let sz = f.read(&mut buf)?;
         let addr = std::str::from_utf8(&buf[0..sz])?;
@@ -263,12 +454,12 @@ impl Win {
         Ok((a[0].parse()?, a[1].parse()?))
     }
     pub fn read(&mut self, file: File) -> Result<&mut Fid> {
-        let f = self.fid(file);
+        let f = self.fid(file)?;
         f.seek(SeekFrom::Start(0))?;
         Ok(f)
     }
     pub fn seek(&mut self, file: File, pos: SeekFrom) -> Result<u64> {
-        let f = self.fid(file);
+        let f = self.fid(file)?;
         Ok(f.seek(pos)?)
     }
 }
@@ -291,18 +482,14 @@ pub struct Event {
 }
 
 impl Event {
-    pub fn load_text(&mut self) {
-        if self.text.len() == 0 && self.q0 < self.q1 {
-            /*
-            w.Addr("#%d,#%d", e.Q0, e.Q1)
-            data, err := w.ReadAll("xdata")
-            if err != nil {
-                w.Err(err.Error())
-            }
-            e.Text = data
-            */
-            panic!("unimplemented");
+    // load_text reads the text of an event whose selection was too long for
+    // acme to include in the event itself.
+    pub fn load_text(&mut self, w: &mut Win) -> Result<()> {
+        if self.text.is_empty() && self.q0 < self.q1 {
+            self.text = w.read_range(self.q0, self.q1)?;
+            self.nr = self.text.chars().count() as u32;
         }
+        Ok(())
     }
 }
 
@@ -321,13 +508,13 @@ impl NlOffsets {
         let mut leftover = 0;
         loop {
             line.clear();
-            let sz = r.read_until('\n' as u8, &mut line)?;
+            let sz = r.read_until(b'\n', &mut line)?;
             if sz == 0 {
                 break;
             }
             let n = std::str::from_utf8(&line)?.chars().count() as u32;
             let last: u8 = *line.last().unwrap();
-            if last != '\n' as u8 {
+            if last != b'\n' {
                 leftover = n;
                 break;
             }
@@ -375,6 +562,7 @@ impl NlOffsets {
 #[cfg(test)]
 mod tests {
     use crate::acme::*;
+    use crate::fakeacme::FakeAcme;
 
     #[test]
     fn nloffsets() {
@@ -392,16 +580,171 @@ mod tests {
         assert_eq!(n.last(), (2, 2));
     }
 
+    #[test]
+    fn ctl_info() {
+        let info: CtlInfo = "          3          32        1204           0           1 "
+            .parse()
+            .unwrap();
+        assert_eq!(info.id, 3);
+        assert_eq!(info.tag_len, 32);
+        assert_eq!(info.body_len, 1204);
+        assert!(!info.isdir);
+        assert!(info.dirty);
+        assert_eq!(info.font, None);
+
+        let info: CtlInfo = "          5           9           0           1           0         \
+            918 '/mnt/font/Go Mono/11a/font'           4 "
+            .parse()
+            .unwrap();
+        assert!(info.isdir);
+        assert_eq!(info.width, Some(918));
+        assert_eq!(info.font.as_deref(), Some("/mnt/font/Go Mono/11a/font"));
+        assert_eq!(info.tabwidth, Some(4));
+
+        assert!("1 2 3".parse::<CtlInfo>().is_err());
+    }
+
+    fn events(stream: &str) -> WinEvents<std::io::Cursor<Vec<u8>>> {
+        WinEvents::new(std::io::Cursor::new(stream.as_bytes().to_vec()))
+    }
+
+    #[test]
+    fn read_event() {
+        let mut wev = events("Mx12 15 0 3 Get\nKI4 5 0 1 é\n");
+        let ev = wev.read_event().unwrap();
+        assert_eq!((ev.c1, ev.c2, ev.q0, ev.q1), ('M', 'x', 12, 15));
+        assert_eq!(ev.text, "Get");
+        let ev = wev.read_event().unwrap();
+        assert_eq!((ev.c1, ev.c2), ('K', 'I'));
+        assert_eq!(ev.nr, 1);
+        assert_eq!(ev.text, "é");
+        assert!(wev.read_event().is_err());
+    }
+
+    #[test]
+    fn read_event_expansion() {
+        // A click in the middle of a word is expanded by a second event.
+        let mut wev = events("ML7 7 2 0 \nML4 12 0 8 größenß\u{1F600}\n");
+        let ev = wev.read_event().unwrap();
+        assert_eq!(ev.c2, 'L');
+        assert_eq!((ev.orig_q0, ev.orig_q1), (7, 7));
+        assert_eq!((ev.q0, ev.q1), (4, 12));
+        assert_eq!(ev.flag, 2);
+        assert_eq!(ev.text, "größenß\u{1F600}");
+    }
+
+    #[test]
+    fn read_event_chorded() {
+        let mut wev = events("Mx0 4 8 4 Edit\nMx0 0 0 6 ,s/ä/b\nMx0 0 0 8 /tmp/ünï\n");
+        let ev = wev.read_event().unwrap();
+        assert_eq!(ev.text, "Edit");
+        assert_eq!(ev.arg, ",s/ä/b");
+        assert_eq!(ev.loc, "/tmp/ünï");
+    }
+
+    #[test]
+    fn read_event_errors() {
+        assert!(events("Mx1 2 0 3 Get \n").read_event().is_err());
+        assert!(events("Mx1 2x 0 3 Get\n").read_event().is_err());
+        assert!(events("Mx1 2 0 257 Get\n").read_event().is_err());
+    }
+
+    #[test]
+    fn write_event() {
+        let mut wev = events("Mx12 15 0 3 Get\n");
+        let ev = wev.read_event().unwrap();
+        wev.write_event(ev).unwrap();
+        // The cursor stands in for acme's event file, so the reply is
+        // written after the event that was read.
+        let buf = wev.event.into_inner().into_inner();
+        assert_eq!(
+            std::str::from_utf8(&buf).unwrap(),
+            "Mx12 15 0 3 Get\nMx12 15 \n"
+        );
+    }
+
+    #[test]
+    fn log_op() {
+        assert_eq!("zerox".parse::<LogOp>().unwrap(), LogOp::Zerox);
+        assert_eq!("focus".parse::<LogOp>().unwrap(), LogOp::Focus);
+        assert_eq!(
+            "rename".parse::<LogOp>().unwrap(),
+            LogOp::Other("rename".to_string())
+        );
+    }
+
+    fn fake() -> (FakeAcme, Arc<Mutex<Fsys>>) {
+        let acme = FakeAcme::new();
+        let fsys = Arc::new(Mutex::new(acme.mount().unwrap()));
+        (acme, fsys)
+    }
+
     #[test]
     fn windows() {
-        let ws = WinInfo::windows().unwrap();
-        assert_ne!(ws.len(), 0);
+        let (acme, fsys) = fake();
+        acme.new_window("/a.rs", "");
+        acme.new_window("/b.rs", "");
+        let ws = WinInfo::windows_in(&fsys).unwrap();
+        let names: Vec<&str> = ws.iter().map(|w| w.name.as_str()).collect();
+        assert_eq!(names, vec!["/a.rs", "/b.rs"]);
     }
 
     #[test]
     fn log() {
-        let mut log = LogReader::new().unwrap();
-        log.read().unwrap();
+        let (acme, fsys) = fake();
+        let mut log = LogReader::new_in(&fsys).unwrap();
+        let id = acme.new_window("/a.rs", "");
+        acme.put(id);
+        let ev = log.read().unwrap();
+        assert_eq!((ev.id, ev.op, ev.name.as_str()), (id, LogOp::New, "/a.rs"));
+        assert_eq!(log.read().unwrap().op, LogOp::Put);
+    }
+
+    #[test]
+    fn win() {
+        let (acme, fsys) = fake();
+        let mut w = Win::new_in(&fsys).unwrap();
+        w.name("/hello.txt").unwrap();
+        w.write(File::Body, "hello wörld\n").unwrap();
+        let id = w.id();
+        assert_eq!(acme.name(id), "/hello.txt");
+
+        let info = w.info().unwrap();
+        assert_eq!(info.id, id);
+        assert_eq!(info.body_len, 12);
+        assert!(info.dirty);
+
+        assert_eq!(w.read_range(6, 11).unwrap(), "wörld");
+        w.replace_range(6, 11, "there").unwrap();
+        assert_eq!(acme.body(id), "hello there\n");
+        assert_eq!(w.read_addr().unwrap(), (11, 11));
+
+        w.err("oops").unwrap();
+        assert_eq!(acme.errors(id), "oops\n");
+
+        w.ctl("clean").unwrap();
+        w.del(false).unwrap();
+        assert!(acme.windows().is_empty());
+    }
+
+    #[test]
+    fn win_events() {
+        let (acme, fsys) = fake();
+        let id = acme.new_window("/a.rs", &"x".repeat(300));
+        let mut w = Win::open(&fsys, id).unwrap();
+        let mut wev = w.events().unwrap();
+        acme.execute(id, "Get");
+        acme.look(id, 0, 300);
+
+        let ev = wev.read_event().unwrap();
+        assert_eq!((ev.c2, ev.text.as_str()), ('x', "Get"));
+        wev.write_event(ev).unwrap();
+
+        let mut ev = wev.read_event().unwrap();
+        assert_eq!((ev.c2, ev.nr), ('L', 0));
+        ev.load_text(&mut w).unwrap();
+        assert_eq!(ev.text.len(), 300);
+        assert_eq!(acme.written_events(id), vec!["Mx0 3 \n"]);
     }
 
     #[test]
@@ -424,7 +767,7 @@ mod tests {
                     wev.write_event(ev).unwrap();
                 }
                 'l' | 'L' => {
-                    ev.load_text();
+                    ev.load_text(&mut w).unwrap();
                     println!("look: {}", ev.text);
                     wev.write_event(ev).unwrap();
                 }
@@ -60,14 +60,14 @@ impl Conn {
                 .lock()
                 .unwrap()
                 .remove(&tag)
-                .expect(format!("expected receiver with tag {:?}", tag).as_str());
+                .unwrap_or_else(|| panic!("expected receiver with tag {:?}", tag));
             cw.lock().unwrap().free_tags.push(tag);
             s.send(data).unwrap();
         });
 
         let (tag, r) = c.new_tag()?;
         let tx = Tversion {
-            tag: tag,
+            tag,
             msize: c.msize,
             version: "9P2000".into(),
         };
@@ -86,7 +86,7 @@ impl Conn {
     fn new_tag(&mut self) -> Result<(u16, Receiver<Vec<u8>>)> {
         let mut cw = self.writer.lock().unwrap();
         let tag: u16;
-        if cw.free_tags.len() > 0 {
+        if !cw.free_tags.is_empty() {
             tag = cw.free_tags.remove(0);
         } else if cw.next_tag == NOTAG {
             bail!("out of tags");
@@ -160,7 +160,7 @@ impl Conn {
     pub fn walk(&mut self, fid: u32, newfid: u32, wname: Vec<String>) -> Result<Vec<Qid>> {
         let (tag, r) = self.new_tag()?;
         let walk = Twalk {
-            tag: tag,
+            tag,
             fid,
             newfid,
             wname,
@@ -170,18 +170,14 @@ impl Conn {
     }
     pub fn open(&mut self, fid: u32, mode: OpenMode) -> Result<()> {
         let (tag, r) = self.new_tag()?;
-        let open = Topen {
-            tag: tag,
-            fid,
-            mode,
-        };
+        let open = Topen { tag, fid, mode };
         self.rpc::<Topen, Ropen>(&open, r)?;
         Ok(())
     }
     pub fn read(&mut self, fid: u32, offset: u64, count: u32) -> Result<Vec<u8>> {
         let (tag, r) = self.new_tag()?;
         let read = Tread {
-            tag: tag,
+            tag,
             fid,
             offset,
             count,
@@ -192,7 +188,7 @@ impl Conn {
     pub fn write(&mut self, fid: u32, offset: u64, data: Vec<u8>) -> Result<u32> {
         let (tag, r) = self.new_tag()?;
         let write = Twrite {
-            tag: tag,
+            tag,
             fid,
             offset,
             data,
@@ -200,9 +196,15 @@ impl Conn {
         let rwrite = self.rpc::<Twrite, Rwrite>(&write, r)?;
         Ok(rwrite.count)
     }
+    pub fn stat(&mut self, fid: u32) -> Result<Stat> {
+        let (tag, r) = self.new_tag()?;
+        let stat = Tstat { tag, fid };
+        let rstat = self.rpc::<Tstat, Rstat>(&stat, r)?;
+        Ok(rstat.stat)
+    }
     pub fn clunk(&mut self, fid: u32) -> Result<()> {
         let (tag, r) = self.new_tag()?;
-        let clunk = Tclunk { tag: tag, fid };
+        let clunk = Tclunk { tag, fid };
         self.rpc::<Tclunk, Rclunk>(&clunk, r)?;
         Ok(())
     }
@@ -210,7 +212,7 @@ impl Conn {
         let newfid = self.newfid();
         let (tag, r) = self.new_tag()?;
         let attach = Tattach {
-            tag: tag,
+            tag,
             fid: newfid,
             afid: NOFID,
             uname: user.into(),
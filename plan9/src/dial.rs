@@ -42,5 +42,5 @@ pub fn namespace() -> String {
     if let Some(m) = DOT_ZERO.captures(disp.as_str()) {
         disp = m.get(1).unwrap().as_str().to_string();
     }
-    format!("/tmp/ns.{}.{}", env::var("USER").unwrap(), disp)
+    format!("/tmp/ns.{}.{}", env::var("USER").unwrap_or_default(), disp)
 }
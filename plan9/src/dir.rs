@@ -0,0 +1,101 @@
+use std::io::Read;
+
+use anyhow::{bail, Result};
+use byteorder::{ByteOrder, LittleEndian};
+use nine::{
+    de::from_reader,
+    p2000::{FileMode, Qid, Stat},
+};
+
+// Dir is a directory entry as returned by stat or by reading a directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dir {
+    pub name: String,
+    pub qid: Qid,
+    pub mode: FileMode,
+    pub length: u64,
+    pub mtime: u32,
+}
+
+impl Dir {
+    pub fn is_dir(&self) -> bool {
+        self.mode.contains(FileMode::DIR)
+    }
+
+    // unmarshal decodes the concatenated stat records returned by a
+    // directory read.
+    pub fn unmarshal(mut data: &[u8]) -> Result<Vec<Dir>> {
+        let mut dirs = vec![];
+        while !data.is_empty() {
+            if data.len() < 2 {
+                bail!("short stat record");
+            }
+            let n = LittleEndian::read_u16(data) as usize + 2;
+            if data.len() < n {
+                bail!("short stat record");
+            }
+            // Directory entries only carry one size prefix, but the nine
+            // deserializer expects the two of an Rstat, so fake the outer one.
+            let stat: Stat = from_reader((&[0u8; 2][..]).chain(&data[..n]))?;
+            dirs.push(stat.into());
+            data = &data[n..];
+        }
+        Ok(dirs)
+    }
+}
+
+impl From<Stat> for Dir {
+    fn from(stat: Stat) -> Self {
+        Dir {
+            name: stat.name.into_owned(),
+            qid: stat.qid,
+            mode: stat.mode,
+            length: stat.length,
+            mtime: stat.mtime,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nine::{p2000::*, ser::into_bytes};
+
+    use crate::dir::*;
+
+    fn stat(name: &str, mode: FileMode, length: u64) -> Stat {
+        Stat {
+            type_: 0,
+            dev: 0,
+            qid: Qid {
+                file_type: mode.into(),
+                version: 0,
+                path: length,
+            },
+            mode,
+            atime: 1,
+            mtime: 2,
+            length,
+            name: name.to_string().into(),
+            uid: "glenda".into(),
+            gid: "glenda".into(),
+            muid: "".into(),
+        }
+    }
+
+    #[test]
+    fn unmarshal() {
+        let mut data = vec![];
+        data.extend(into_bytes(&stat("1", FileMode::DIR | FileMode::PERM_MASK, 0)).unwrap());
+        data.extend(into_bytes(&stat("index", FileMode::OWNER_READ, 42)).unwrap());
+        let dirs = Dir::unmarshal(&data).unwrap();
+        assert_eq!(dirs.len(), 2);
+        assert_eq!(dirs[0].name, "1");
+        assert!(dirs[0].is_dir());
+        assert_eq!(dirs[1].name, "index");
+        assert!(!dirs[1].is_dir());
+        assert_eq!(dirs[1].length, 42);
+        assert_eq!(dirs[1].mtime, 2);
+
+        assert!(Dir::unmarshal(&data[..data.len() - 1]).is_err());
+    }
+}
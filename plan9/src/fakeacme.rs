@@ -0,0 +1,999 @@
+// An in-memory acme that serves the acme file system over 9P, for testing
+// programs that talk to acme on machines without plan9port.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use anyhow::{bail, Result};
+use nine::p2000::*;
+use regex::Regex;
+
+use crate::{
+    conn::Conn,
+    fsys::Fsys,
+    srv::{self, Srv},
+};
+
+const EVENT_SIZE: usize = 256;
+
+// quote quotes s like acme's %q if it has spaces or quotes.
+fn quote(s: &str) -> String {
+    if s.contains([' ', '\'']) {
+        format!("'{}'", s.replace('\'', "''"))
+    } else {
+        s.to_string()
+    }
+}
+
+// FakeAcme is an acme with no user interface. Tests drive it through its
+// methods, which stand in for what a user would do, while the program under
+// test talks to it through the file system returned by mount.
+#[derive(Clone)]
+pub struct FakeAcme {
+    acme: Arc<Acme>,
+}
+
+struct Acme {
+    state: Mutex<State>,
+    // Signalled whenever the log or a window's events change.
+    cond: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: usize,
+    wins: BTreeMap<usize, Window>,
+    // Files are shared by a window and its zerox clones.
+    files: HashMap<usize, Text>,
+    log: Vec<String>,
+}
+
+struct Window {
+    file: usize,
+    tag: String,
+    addr: (usize, usize),
+    dot: (usize, usize),
+    nomark: bool,
+    events: VecDeque<String>,
+    written: Vec<String>,
+    errors: String,
+    // The font and the width of a tab in pixels.
+    font: (String, usize),
+}
+
+#[derive(Default)]
+struct Text {
+    name: String,
+    body: String,
+    dirty: bool,
+}
+
+impl Default for FakeAcme {
+    fn default() -> Self {
+        FakeAcme::new()
+    }
+}
+
+impl FakeAcme {
+    pub fn new() -> FakeAcme {
+        FakeAcme {
+            acme: Arc::new(Acme {
+                state: Mutex::new(State {
+                    next_id: 1,
+                    ..Default::default()
+                }),
+                cond: Condvar::new(),
+            }),
+        }
+    }
+
+    // mount returns a new connection to the fake acme's file system.
+    pub fn mount(&self) -> Result<Fsys> {
+        let (client, server) = UnixStream::pair()?;
+        let acme = Arc::clone(&self.acme);
+        thread::Builder::new()
+            .name("FakeAcme".to_string())
+            .spawn(move || {
+                let _ = srv::serve(acme, server);
+            })?;
+        Conn::new(client)?.attach("acme".to_string(), "".to_string())
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.acme.state.lock().unwrap()
+    }
+
+    fn update<T>(&self, f: impl FnOnce(&mut State) -> T) -> T {
+        let v = f(&mut self.state());
+        self.acme.cond.notify_all();
+        v
+    }
+
+    // new_window opens a window on a file named name holding body.
+    pub fn new_window(&self, name: &str, body: &str) -> usize {
+        self.update(|st| {
+            let id = st.new_window();
+            let w = &st.wins[&id];
+            let text = st.files.get_mut(&w.file).unwrap();
+            text.name = name.to_string();
+            text.body = body.to_string();
+            st.log(id, "new");
+            id
+        })
+    }
+
+    pub fn windows(&self) -> Vec<usize> {
+        self.state().wins.keys().cloned().collect()
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        let st = self.state();
+        st.wins
+            .iter()
+            .find(|(_, w)| st.files[&w.file].name == name)
+            .map(|(id, _)| *id)
+    }
+
+    pub fn name(&self, id: usize) -> String {
+        self.text(id, |t| t.name.clone())
+    }
+
+    pub fn body(&self, id: usize) -> String {
+        self.text(id, |t| t.body.clone())
+    }
+
+    pub fn dirty(&self, id: usize) -> bool {
+        self.text(id, |t| t.dirty)
+    }
+
+    pub fn tag(&self, id: usize) -> String {
+        let st = self.state();
+        st.tag(&st.wins[&id])
+    }
+
+    // errors returns what was written to the window's errors file.
+    pub fn errors(&self, id: usize) -> String {
+        self.state().wins[&id].errors.clone()
+    }
+
+    fn text<T>(&self, id: usize, f: impl FnOnce(&Text) -> T) -> T {
+        let st = self.state();
+        f(&st.files[&st.wins[&id].file])
+    }
+
+    pub fn set_dot(&self, id: usize, q0: usize, q1: usize) {
+        self.update(|st| st.wins.get_mut(&id).unwrap().dot = (q0, q1));
+    }
+
+    // set_font sets the window's font and the width of its tabs in pixels.
+    pub fn set_font(&self, id: usize, font: &str, tabwidth: usize) {
+        self.update(|st| st.wins.get_mut(&id).unwrap().font = (font.to_string(), tabwidth));
+    }
+
+    // get replaces the window's body as if it were reloaded from disk.
+    pub fn get(&self, id: usize, body: &str) {
+        self.update(|st| {
+            let file = st.wins[&id].file;
+            let text = st.files.get_mut(&file).unwrap();
+            text.body = body.to_string();
+            text.dirty = false;
+            st.log(id, "get");
+        });
+    }
+
+    // rename changes the name of the window's file. acme doesn't log this.
+    pub fn rename(&self, id: usize, name: &str) {
+        self.update(|st| {
+            let file = st.wins[&id].file;
+            st.files.get_mut(&file).unwrap().name = name.to_string();
+        });
+    }
+
+    pub fn zerox(&self, id: usize) -> usize {
+        self.update(|st| {
+            let file = st.wins[&id].file;
+            let nid = st.new_window();
+            st.files.remove(&st.wins[&nid].file);
+            st.wins.get_mut(&nid).unwrap().file = file;
+            st.log(nid, "zerox");
+            nid
+        })
+    }
+
+    pub fn put(&self, id: usize) {
+        self.update(|st| {
+            let file = st.wins[&id].file;
+            st.files.get_mut(&file).unwrap().dirty = false;
+            st.log(id, "put");
+        });
+    }
+
+    pub fn focus(&self, id: usize) {
+        self.update(|st| st.log(id, "focus"));
+    }
+
+    pub fn del(&self, id: usize) {
+        self.update(|st| st.del(id));
+    }
+
+    // event queues a raw event to be read from the window's event file. kind
+    // is the event's origin and type, like "Mx" for a button 2 click.
+    pub fn event(&self, id: usize, kind: &str, q0: usize, q1: usize, flag: u32, text: &str) {
+        // Like acme, leave out text that is too long and let the reader
+        // fetch it.
+        let text = if text.chars().count() > EVENT_SIZE {
+            ""
+        } else {
+            text
+        };
+        let ev = format!(
+            "{}{} {} {} {} {}\n",
+            kind,
+            q0,
+            q1,
+            flag,
+            text.chars().count(),
+            text
+        );
+        self.update(|st| st.wins.get_mut(&id).unwrap().events.push_back(ev));
+    }
+
+    // look sends a button 3 event for the body text between q0 and q1.
+    pub fn look(&self, id: usize, q0: usize, q1: usize) {
+        let text: String = self.body(id).chars().skip(q0).take(q1 - q0).collect();
+        self.event(id, "ML", q0, q1, 0, &text);
+    }
+
+    // execute sends a button 2 event for cmd typed in the window's tag.
+    pub fn execute(&self, id: usize, cmd: &str) {
+        self.event(id, "Mx", 0, cmd.chars().count(), 0, cmd);
+    }
+
+    // written_events returns the events written back to acme by the window's
+    // event file.
+    pub fn written_events(&self, id: usize) -> Vec<String> {
+        self.state().wins[&id].written.clone()
+    }
+}
+
+impl State {
+    fn new_window(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.files.insert(id, Text::default());
+        self.wins.insert(
+            id,
+            Window {
+                file: id,
+                tag: "".to_string(),
+                addr: (0, 0),
+                dot: (0, 0),
+                nomark: false,
+                events: VecDeque::new(),
+                written: vec![],
+                errors: "".to_string(),
+                font: ("/lib/font/bit/lucsans/euro.8.font".to_string(), 4),
+            },
+        );
+        id
+    }
+
+    fn del(&mut self, id: usize) {
+        if let Some(w) = self.wins.remove(&id) {
+            if !self.wins.values().any(|o| o.file == w.file) {
+                self.files.remove(&w.file);
+            }
+            self.log(id, "del");
+        }
+    }
+
+    fn log(&mut self, id: usize, op: &str) {
+        let name = self
+            .wins
+            .get(&id)
+            .map_or("".to_string(), |w| self.files[&w.file].name.clone());
+        self.log.push(format!("{} {} {}\n", id, op, name));
+    }
+
+    fn win(&mut self, id: usize) -> Result<(&mut Window, &mut Text)> {
+        let w = match self.wins.get_mut(&id) {
+            Some(w) => w,
+            None => bail!("window deleted"),
+        };
+        let t = self.files.get_mut(&w.file).unwrap();
+        Ok((w, t))
+    }
+
+    fn tag(&self, w: &Window) -> String {
+        format!("{} Del Snarf | Look {}", self.files[&w.file].name, w.tag)
+    }
+
+    fn ctl(&self, id: usize) -> String {
+        let w = &self.wins[&id];
+        let t = &self.files[&w.file];
+        format!(
+            "{:11} {:11} {:11} {:11} {:11} {:11} {} {:11} ",
+            id,
+            self.tag(w).chars().count(),
+            t.body.chars().count(),
+            0,
+            t.dirty as u8,
+            640,
+            quote(&w.font.0),
+            w.font.1,
+        )
+    }
+
+    fn index(&self) -> String {
+        let mut s = String::new();
+        for (id, w) in &self.wins {
+            let t = &self.files[&w.file];
+            s.push_str(&format!(
+                "{:11} {:11} {:11} {:11} {:11} {}\n",
+                id,
+                self.tag(w).chars().count(),
+                t.body.chars().count(),
+                0,
+                t.dirty as u8,
+                self.tag(w),
+            ));
+        }
+        s
+    }
+}
+
+impl Window {
+    fn ctl(&mut self, text: &mut Text, cmd: &str) -> Result<bool> {
+        let mut deleted = false;
+        match cmd {
+            "" | "show" | "mark" => {}
+            "clean" => text.dirty = false,
+            "dirty" => text.dirty = true,
+            // Load the file from disk.
+            "get" => {
+                text.body = std::fs::read_to_string(&text.name)?;
+                text.dirty = false;
+            }
+            "cleartag" => self.tag.clear(),
+            "nomark" => self.nomark = true,
+            "addr=dot" => self.addr = self.dot,
+            "dot=addr" => self.dot = self.addr,
+            "del" if text.dirty => bail!("file is dirty"),
+            "del" | "delete" => deleted = true,
+            _ => match cmd.strip_prefix("name ") {
+                Some(name) => text.name = name.to_string(),
+                None => bail!("bad control message"),
+            },
+        }
+        Ok(deleted)
+    }
+
+    // replace replaces the text between q0 and q1 like a write to acme's
+    // data file, which leaves addr at the end of the inserted text.
+    fn replace(&mut self, text: &mut Text, q0: usize, q1: usize, s: &str) -> Result<()> {
+        let nc = text.body.chars().count();
+        if q0 > q1 || q1 > nc {
+            bail!("address out of range");
+        }
+        let b0 = byte_offset(&text.body, q0);
+        let b1 = byte_offset(&text.body, q1);
+        text.body.replace_range(b0..b1, s);
+        text.dirty = true;
+        let n = s.chars().count();
+        let adjust = |q: usize| {
+            if q >= q1 {
+                q - (q1 - q0) + n
+            } else if q > q0 {
+                q0
+            } else {
+                q
+            }
+        };
+        self.dot = (adjust(self.dot.0), adjust(self.dot.1));
+        self.addr = (q0 + n, q0 + n);
+        Ok(())
+    }
+}
+
+// Node is a file in the acme file system.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Node {
+    Root,
+    Index,
+    Log,
+    New,
+    NewCtl,
+    Dir(usize),
+    File(usize, WinFile),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum WinFile {
+    Ctl,
+    Body,
+    Addr,
+    Data,
+    Xdata,
+    Tag,
+    Event,
+    Errors,
+    Rdsel,
+    Wrsel,
+    Editout,
+}
+
+const WIN_FILES: [(&str, WinFile); 11] = [
+    ("ctl", WinFile::Ctl),
+    ("body", WinFile::Body),
+    ("addr", WinFile::Addr),
+    ("data", WinFile::Data),
+    ("xdata", WinFile::Xdata),
+    ("tag", WinFile::Tag),
+    ("event", WinFile::Event),
+    ("errors", WinFile::Errors),
+    ("rdsel", WinFile::Rdsel),
+    ("wrsel", WinFile::Wrsel),
+    ("editout", WinFile::Editout),
+];
+
+impl Node {
+    fn name(&self) -> String {
+        match self {
+            Node::Root => "/".to_string(),
+            Node::Index => "index".to_string(),
+            Node::Log => "log".to_string(),
+            Node::New => "new".to_string(),
+            Node::NewCtl => "ctl".to_string(),
+            Node::Dir(id) => id.to_string(),
+            Node::File(_, f) => WIN_FILES
+                .iter()
+                .find(|(_, wf)| wf == f)
+                .unwrap()
+                .0
+                .to_string(),
+        }
+    }
+    fn is_dir(&self) -> bool {
+        matches!(self, Node::Root | Node::New | Node::Dir(_))
+    }
+    fn qid(&self) -> Qid {
+        let path = match self {
+            Node::Root => 0,
+            Node::Index => 1,
+            Node::Log => 2,
+            Node::New => 3,
+            Node::NewCtl => 4,
+            Node::Dir(id) => (*id as u64) << 8,
+            Node::File(id, f) => {
+                ((*id as u64) << 8)
+                    + 1
+                    + WIN_FILES.iter().position(|(_, wf)| wf == f).unwrap() as u64
+            }
+        };
+        Qid {
+            file_type: if self.is_dir() {
+                FileType::DIR
+            } else {
+                FileType::FILE
+            },
+            version: 0,
+            path,
+        }
+    }
+    fn stat(&self, st: &State) -> Stat {
+        let (mode, length) = if self.is_dir() {
+            (FileMode::DIR | FileMode::from_bits_truncate(0o500), 0)
+        } else if let Node::File(id, WinFile::Body) = self {
+            let length = st.wins.get(id).map_or(0, |w| st.files[&w.file].body.len());
+            (FileMode::from_bits_truncate(0o600), length as u64)
+        } else {
+            (FileMode::from_bits_truncate(0o600), 0)
+        };
+        Stat {
+            type_: 0,
+            dev: 0,
+            qid: self.qid(),
+            mode,
+            atime: 0,
+            mtime: 0,
+            length,
+            name: self.name().into(),
+            uid: "acme".into(),
+            gid: "acme".into(),
+            muid: "".into(),
+        }
+    }
+    fn walk(&self, st: &State, name: &str) -> Option<Node> {
+        match (self, name) {
+            (Node::Root, "index") => Some(Node::Index),
+            (Node::Root, "log") => Some(Node::Log),
+            (Node::Root, "new") => Some(Node::New),
+            (Node::Root, _) => {
+                let id = name.parse().ok()?;
+                if st.wins.contains_key(&id) {
+                    Some(Node::Dir(id))
+                } else {
+                    None
+                }
+            }
+            (Node::New, "ctl") => Some(Node::NewCtl),
+            (Node::Dir(id), _) => WIN_FILES
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, f)| Node::File(*id, *f)),
+            _ => None,
+        }
+    }
+    fn children(&self, st: &State) -> Vec<Node> {
+        match self {
+            Node::Root => {
+                let mut v = vec![Node::Index, Node::Log, Node::New];
+                v.extend(st.wins.keys().map(|id| Node::Dir(*id)));
+                v
+            }
+            Node::New => vec![Node::NewCtl],
+            Node::Dir(id) => WIN_FILES.iter().map(|(_, f)| Node::File(*id, *f)).collect(),
+            _ => vec![],
+        }
+    }
+}
+
+// FidState is shared by the copies of a fid that reads are given.
+#[derive(Clone)]
+struct FidState {
+    node: Node,
+    // The next log entry to return.
+    log_pos: Arc<AtomicUsize>,
+    // Incremented by each flush, to wake a waiting read.
+    flushes: Arc<AtomicUsize>,
+}
+
+impl FidState {
+    fn new(node: Node) -> FidState {
+        FidState {
+            node,
+            log_pos: Arc::new(AtomicUsize::new(0)),
+            flushes: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+impl Srv for Acme {
+    type File = FidState;
+
+    fn attach(&self, _uname: &str, _aname: &str) -> Result<FidState> {
+        Ok(FidState::new(Node::Root))
+    }
+
+    fn walk(&self, dir: &FidState, name: &str) -> Result<FidState> {
+        match dir.node.walk(&self.state.lock().unwrap(), name) {
+            Some(node) => Ok(FidState::new(node)),
+            None => bail!("file does not exist"),
+        }
+    }
+
+    fn open(&self, f: &mut FidState, _mode: OpenMode) -> Result<()> {
+        let mut st = self.state.lock().unwrap();
+        match f.node {
+            // Opening new/ctl creates a window.
+            Node::NewCtl => {
+                let id = st.new_window();
+                st.log(id, "new");
+                self.cond.notify_all();
+                f.node = Node::File(id, WinFile::Ctl);
+            }
+            Node::Log => f.log_pos = Arc::new(AtomicUsize::new(st.log.len())),
+            Node::File(id, _) if !st.wins.contains_key(&id) => bail!("window deleted"),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn read(&self, f: &FidState, offset: u64, count: u32) -> Result<Vec<u8>> {
+        let flushes = f.flushes.load(Ordering::SeqCst);
+        let mut st = self.state.lock().unwrap();
+        let count = count as usize;
+        let data = match f.node {
+            Node::Index => st.index().into_bytes(),
+            // Reads from log and event wait for something to happen, or to
+            // be flushed.
+            Node::Log => {
+                let pos = f.log_pos.load(Ordering::SeqCst);
+                while st.log.len() <= pos {
+                    if f.flushes.load(Ordering::SeqCst) != flushes {
+                        bail!("interrupted");
+                    }
+                    st = self.cond.wait(st).unwrap();
+                }
+                f.log_pos.store(pos + 1, Ordering::SeqCst);
+                return Ok(st.log[pos].clone().into_bytes());
+            }
+            Node::File(id, WinFile::Ctl) => st.ctl(id).into_bytes(),
+            Node::File(id, WinFile::Event) => {
+                loop {
+                    let (w, _) = st.win(id)?;
+                    if !w.events.is_empty() {
+                        break;
+                    }
+                    if f.flushes.load(Ordering::SeqCst) != flushes {
+                        bail!("interrupted");
+                    }
+                    st = self.cond.wait(st).unwrap();
+                }
+                let (w, _) = st.win(id)?;
+                let mut data = vec![];
+                while let Some(ev) = w.events.front() {
+                    if !data.is_empty() && data.len() + ev.len() > count {
+                        break;
+                    }
+                    data.extend(w.events.pop_front().unwrap().into_bytes());
+                }
+                return Ok(data);
+            }
+            Node::File(id, file) => {
+                let tag = match st.wins.get(&id) {
+                    Some(w) => st.tag(w),
+                    None => bail!("window deleted"),
+                };
+                let (w, text) = st.win(id)?;
+                match file {
+                    WinFile::Body => text.body.clone().into_bytes(),
+                    WinFile::Tag => tag.into_bytes(),
+                    WinFile::Addr => format!("{:11} {:11} ", w.addr.0, w.addr.1).into_bytes(),
+                    WinFile::Rdsel => runes(&text.body, w.dot.0, w.dot.1).into_bytes(),
+                    // Reads from data and xdata advance addr.
+                    WinFile::Data | WinFile::Xdata => {
+                        let end = if file == WinFile::Xdata {
+                            w.addr.1
+                        } else {
+                            text.body.chars().count()
+                        };
+                        let mut s = String::new();
+                        for c in text
+                            .body
+                            .chars()
+                            .skip(w.addr.0)
+                            .take(end.saturating_sub(w.addr.0))
+                        {
+                            if s.len() + c.len_utf8() > count {
+                                break;
+                            }
+                            s.push(c);
+                        }
+                        w.addr.0 += s.chars().count();
+                        return Ok(s.into_bytes());
+                    }
+                    _ => bail!("permission denied"),
+                }
+            }
+            Node::NewCtl => vec![],
+            _ => bail!("permission denied"),
+        };
+        let off = (offset as usize).min(data.len());
+        let end = (off + count).min(data.len());
+        Ok(data[off..end].to_vec())
+    }
+
+    fn read_dir(&self, dir: &FidState) -> Result<Vec<Stat>> {
+        let st = self.state.lock().unwrap();
+        Ok(dir.node.children(&st).iter().map(|n| n.stat(&st)).collect())
+    }
+
+    fn write(&self, f: &mut FidState, _offset: u64, data: &[u8]) -> Result<u32> {
+        let s = std::str::from_utf8(data)?;
+        let mut st = self.state.lock().unwrap();
+        let (id, file) = match f.node {
+            Node::File(id, file) => (id, file),
+            _ => bail!("permission denied"),
+        };
+        let (w, text) = st.win(id)?;
+        match file {
+            WinFile::Ctl => {
+                let mut deleted = false;
+                for cmd in s.lines() {
+                    deleted |= w.ctl(text, cmd)?;
+                }
+                if deleted {
+                    st.del(id);
+                }
+            }
+            WinFile::Body => {
+                text.body.push_str(s);
+                text.dirty = true;
+            }
+            WinFile::Tag => w.tag.push_str(s),
+            WinFile::Addr => w.addr = eval_addr(&text.body, w.addr, s)?,
+            WinFile::Data | WinFile::Xdata => {
+                let (q0, q1) = w.addr;
+                w.replace(text, q0, q1, s)?;
+            }
+            WinFile::Wrsel => {
+                let (q0, q1) = w.dot;
+                w.replace(text, q0, q1, s)?;
+                w.dot = (q0, q0 + s.chars().count());
+            }
+            WinFile::Event => w.written.extend(s.lines().map(|l| format!("{}\n", l))),
+            WinFile::Errors => w.errors.push_str(s),
+            WinFile::Rdsel | WinFile::Editout => bail!("permission denied"),
+        }
+        self.cond.notify_all();
+        Ok(data.len() as u32)
+    }
+
+    fn stat(&self, f: &FidState) -> Result<Stat> {
+        Ok(f.node.stat(&self.state.lock().unwrap()))
+    }
+
+    fn flush(&self, _tag: u16, f: &FidState) {
+        // Take the lock so a read can't miss the wakeup between checking
+        // flushes and waiting.
+        let _st = self.state.lock().unwrap();
+        f.flushes.fetch_add(1, Ordering::SeqCst);
+        self.cond.notify_all();
+    }
+}
+
+fn byte_offset(s: &str, q: usize) -> usize {
+    s.char_indices().nth(q).map_or(s.len(), |(i, _)| i)
+}
+
+fn runes(s: &str, q0: usize, q1: usize) -> String {
+    s.chars().skip(q0).take(q1.saturating_sub(q0)).collect()
+}
+
+// eval_addr evaluates an address written to a window's addr file. Addresses
+// are relative to dot, which is the current value of addr.
+fn eval_addr(text: &str, dot: (usize, usize), addr: &str) -> Result<(usize, usize)> {
+    let mut a = Addr {
+        text,
+        chars: text.chars().collect(),
+        s: addr.trim_end_matches('\n').chars().collect(),
+        i: 0,
+    };
+    let r = a.range(dot)?;
+    if a.i != a.s.len() {
+        bail!("bad address syntax");
+    }
+    Ok(r.unwrap_or(dot))
+}
+
+struct Addr<'a> {
+    text: &'a str,
+    chars: Vec<char>,
+    s: Vec<char>,
+    i: usize,
+}
+
+impl<'a> Addr<'a> {
+    fn peek(&self) -> Option<char> {
+        self.s.get(self.i).cloned()
+    }
+
+    fn range(&mut self, dot: (usize, usize)) -> Result<Option<(usize, usize)>> {
+        let a = self.compound(dot)?;
+        match self.peek() {
+            Some(c) if c == ',' || c == ';' => {
+                self.i += 1;
+                let left = a.unwrap_or((0, 0));
+                let dot = if c == ';' { left } else { dot };
+                let nc = self.chars.len();
+                let right = self.range(dot)?.unwrap_or((nc, nc));
+                if left.0 > right.1 {
+                    bail!("addresses out of order");
+                }
+                Ok(Some((left.0, right.1)))
+            }
+            _ => Ok(a),
+        }
+    }
+
+    fn compound(&mut self, dot: (usize, usize)) -> Result<Option<(usize, usize)>> {
+        let mut a = self.simple(dot, 0)?;
+        loop {
+            let sign = match self.peek() {
+                Some('+') => 1,
+                Some('-') => -1,
+                _ => return Ok(a),
+            };
+            self.i += 1;
+            let base = a.unwrap_or(dot);
+            a = Some(match self.simple(base, sign)? {
+                Some(b) => b,
+                None => self.line(base, 1, sign)?,
+            });
+        }
+    }
+
+    fn simple(&mut self, base: (usize, usize), sign: i32) -> Result<Option<(usize, usize)>> {
+        let nc = self.chars.len();
+        match self.peek() {
+            Some('#') => {
+                self.i += 1;
+                let n = self.number().unwrap_or(1);
+                let q = match sign {
+                    0 => Some(n),
+                    1 => base.1.checked_add(n),
+                    _ => base.0.checked_sub(n),
+                };
+                match q {
+                    Some(q) if q <= nc => Ok(Some((q, q))),
+                    _ => bail!("address out of range"),
+                }
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let n = self.number().unwrap();
+                self.line(base, n, sign).map(Some)
+            }
+            Some('$') => {
+                self.i += 1;
+                Ok(Some((nc, nc)))
+            }
+            Some('.') => {
+                self.i += 1;
+                Ok(Some(base))
+            }
+            Some(d) if d == '/' || d == '?' => {
+                self.i += 1;
+                let mut re = String::new();
+                while let Some(c) = self.peek() {
+                    self.i += 1;
+                    if c == d {
+                        break;
+                    }
+                    if c == '\\' && self.peek() == Some(d) {
+                        self.i += 1;
+                        re.push(d);
+                        continue;
+                    }
+                    re.push(c);
+                }
+                let reverse = (d == '?') != (sign < 0);
+                self.search(base, &re, reverse).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.i;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.i += 1;
+        }
+        self.s[start..self.i]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .ok()
+    }
+
+    // line is a port of sam's lineaddr.
+    fn line(&self, base: (usize, usize), l: usize, sign: i32) -> Result<(usize, usize)> {
+        let nc = self.chars.len();
+        let c = |p: usize| self.chars[p];
+        let mut p;
+        let q0;
+        if sign >= 0 {
+            if l == 0 {
+                if sign == 0 || base.1 == 0 {
+                    return Ok((0, 0));
+                }
+                q0 = base.1;
+                p = base.1 - 1;
+            } else {
+                let mut n;
+                if sign == 0 || base.1 == 0 {
+                    p = 0;
+                    n = 1;
+                } else {
+                    p = base.1 - 1;
+                    n = (c(p) == '\n') as usize;
+                    p += 1;
+                }
+                while n < l {
+                    if p >= nc {
+                        bail!("address out of range");
+                    }
+                    if c(p) == '\n' {
+                        n += 1;
+                    }
+                    p += 1;
+                }
+                q0 = p;
+            }
+            while p < nc {
+                p += 1;
+                if c(p - 1) == '\n' {
+                    break;
+                }
+            }
+            Ok((q0, p))
+        } else {
+            p = base.0;
+            let q1;
+            if l == 0 {
+                q1 = base.0;
+            } else {
+                let mut n = 0;
+                while n < l {
+                    if p == 0 {
+                        n += 1;
+                        if n != l {
+                            bail!("address out of range");
+                        }
+                    } else {
+                        if c(p - 1) == '\n' {
+                            n += 1;
+                        }
+                        if c(p - 1) != '\n' || n != l {
+                            p -= 1;
+                        }
+                    }
+                }
+                q1 = p;
+                p = p.saturating_sub(1);
+            }
+            while p > 0 && c(p - 1) != '\n' {
+                p -= 1;
+            }
+            Ok((p, q1))
+        }
+    }
+
+    fn search(&self, base: (usize, usize), re: &str, reverse: bool) -> Result<(usize, usize)> {
+        let re = Regex::new(re)?;
+        let to_rune = |b: usize| self.text[..b].chars().count();
+        let m = if reverse {
+            let start = byte_offset(self.text, base.0);
+            let all: Vec<_> = re.find_iter(self.text).collect();
+            all.iter()
+                .rev()
+                .find(|m| m.end() <= start && m.start() < start)
+                .or_else(|| all.last())
+                .cloned()
+        } else {
+            let start = byte_offset(self.text, base.1);
+            re.find_at(self.text, start)
+                .filter(|m| m.end() > start || m.start() > base.0)
+                .or_else(|| re.find(self.text))
+        };
+        match m {
+            Some(m) => Ok((to_rune(m.start()), to_rune(m.end()))),
+            None => bail!("no match for regexp"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fakeacme::*;
+
+    #[test]
+    fn addr() {
+        let text = "one\ntwö\nthree\n";
+        let a = |dot, s| eval_addr(text, dot, s).unwrap();
+        assert_eq!(a((0, 0), ","), (0, 14));
+        assert_eq!(a((0, 0), "#2,#5"), (2, 5));
+        assert_eq!(a((0, 0), "2"), (4, 8));
+        assert_eq!(a((0, 0), "2,2"), (4, 8));
+        assert_eq!(a((0, 0), "0"), (0, 0));
+        assert_eq!(a((0, 0), "$"), (14, 14));
+        assert_eq!(a((0, 0), "1+#0"), (4, 4));
+        assert_eq!(a((0, 0), "0+#0"), (0, 0));
+        assert_eq!(a((0, 0), "2-#1"), (3, 3));
+        assert_eq!(a((4, 8), "."), (4, 8));
+        assert_eq!(a((4, 8), "+"), (8, 14));
+        assert_eq!(a((4, 8), "-"), (0, 4));
+        assert_eq!(a((0, 0), "/thr/"), (8, 11));
+        assert_eq!(a((9, 9), "?o?"), (0, 1));
+        assert_eq!(a((14, 14), "?e?"), (12, 13));
+        assert_eq!(a((0, 0), "/w/;/e/"), (5, 12));
+        assert_eq!(a((0, 0), "2;+"), (4, 14));
+        assert!(eval_addr(text, (0, 0), "#99").is_err());
+        assert!(eval_addr(text, (0, 0), "9").is_err());
+        assert!(eval_addr(text, (0, 0), "/nope/").is_err());
+        assert!(eval_addr(text, (0, 0), "#3,#1").is_err());
+    }
+}
@@ -2,10 +2,10 @@ use std::cmp;
 use std::env;
 use std::io;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use nine::p2000::{OpenMode, Qid};
 
-use crate::conn::Conn;
+use crate::{conn::Conn, dir::Dir};
 
 pub fn get_user() -> String {
     env::var("USER").unwrap()
@@ -37,7 +37,7 @@ impl Fid {
         let name = String::from(name);
         let mut elem: Vec<String> = name
             .split("/")
-            .filter(|&x| x != "" && x != ".")
+            .filter(|&x| !x.is_empty() && x != ".")
             .map(|x| x.to_string())
             .collect();
         let mut qid: Qid;
@@ -48,12 +48,17 @@ impl Fid {
             let wname = elem[0..n].to_vec();
             elem.drain(0..n);
             let qids = self.c.walk(fid, wfid, wname)?;
+            // A partial walk means the file doesn't exist and newfid wasn't
+            // created.
+            if qids.len() < n {
+                bail!("file does not exist");
+            }
             qid = if n == 0 {
                 self.qid.clone()
             } else {
                 qids[n - 1].clone()
             };
-            if elem.len() == 0 {
+            if elem.is_empty() {
                 break;
             }
             fid = wfid;
@@ -66,6 +71,24 @@ impl Fid {
         self.mode = mode;
         Ok(())
     }
+
+    pub fn stat(&mut self) -> Result<Dir> {
+        Ok(self.c.stat(self.fid)?.into())
+    }
+
+    // read_dir reads the remaining entries of an open directory.
+    pub fn read_dir(&mut self) -> Result<Vec<Dir>> {
+        let mut buf = vec![0; (self.c.msize - IOHDRSZ) as usize];
+        let mut dirs = vec![];
+        loop {
+            let n = io::Read::read(self, &mut buf)?;
+            if n == 0 {
+                break;
+            }
+            dirs.extend(Dir::unmarshal(&buf[..n])?);
+        }
+        Ok(dirs)
+    }
 }
 
 const IOHDRSZ: u32 = 24;
@@ -76,7 +99,7 @@ impl io::Read for Fid {
         let n: u32 = cmp::min(buf.len() as u32, msize);
         let data = match self.c.read(self.fid, self.offset, n) {
             Ok(r) => r,
-            Err(e) => return Err(io::Error::new(io::ErrorKind::Other, format!("{}", e))),
+            Err(e) => return Err(io::Error::other(format!("{}", e))),
         };
         for (i, x) in data.iter().enumerate() {
             buf[i] = *x
@@ -90,24 +113,34 @@ impl io::Seek for Fid {
     fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
         match pos {
             io::SeekFrom::Start(n) => self.offset = n,
-            io::SeekFrom::Current(n) => {
-                if n >= 0 {
-                    self.offset += n as u64;
-                } else {
-                    self.offset -= n as u64;
-                }
-            }
-            io::SeekFrom::End(_) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("seeking to end unsupported"),
-                ))
+            io::SeekFrom::Current(n) => self.offset = seek_offset(self.offset, n)?,
+            io::SeekFrom::End(n) => {
+                let d = match self.stat() {
+                    Ok(d) => d,
+                    Err(e) => return Err(io::Error::other(format!("{}", e))),
+                };
+                self.offset = seek_offset(d.length, n)?;
             }
         }
         Ok(self.offset)
     }
 }
 
+fn seek_offset(base: u64, n: i64) -> io::Result<u64> {
+    let off = if n >= 0 {
+        base.checked_add(n as u64)
+    } else {
+        base.checked_sub(n.unsigned_abs())
+    };
+    match off {
+        Some(off) => Ok(off),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        )),
+    }
+}
+
 impl io::Write for Fid {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let msize = (self.c.msize - IOHDRSZ) as usize;
@@ -121,7 +154,7 @@ impl io::Write for Fid {
                 .write(self.fid, self.offset, buf[tot..tot + want].to_vec())
             {
                 Ok(r) => r as usize,
-                Err(e) => return Err(io::Error::new(io::ErrorKind::Other, format!("{}", e))),
+                Err(e) => return Err(io::Error::other(format!("{}", e))),
             };
             tot += got;
             self.offset += got as u64;
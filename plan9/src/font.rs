@@ -0,0 +1,229 @@
+// Reading Plan 9 fonts, enough to find the widths of their characters.
+
+use std::env;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use nine::p2000::OpenMode;
+
+use crate::dial;
+
+// char_width returns the width in pixels of c in the font named name.
+pub fn char_width(name: &str, c: char) -> Result<u32> {
+    // plan9port scales fonts named like 2*font on high dpi displays.
+    if let Some((scale, name)) = name.split_once('*') {
+        if let Ok(scale) = scale.parse::<u32>() {
+            return Ok(scale * char_width(name, c)?);
+        }
+    }
+    let font = String::from_utf8(read(name)?)?;
+    let mut sp = font.split_whitespace();
+    // Skip the height and ascent.
+    for _ in 0..2 {
+        parse_int(sp.next().unwrap_or_default())?;
+    }
+    // Then come ranges of characters and the subfonts holding them.
+    let bad = || anyhow::anyhow!("{}: bad font", name);
+    while let Some(min) = sp.next() {
+        let min = parse_int(min)?;
+        let max = parse_int(sp.next().ok_or_else(bad)?)?;
+        let mut subfont = sp.next().ok_or_else(bad)?;
+        let mut offset = 0;
+        if subfont.starts_with(|c: char| c.is_ascii_digit()) {
+            offset = parse_int(subfont)?;
+            subfont = sp.next().ok_or_else(bad)?;
+        }
+        let i = c as i64;
+        if i < min || i > max {
+            continue;
+        }
+        return subfont_width(&subfont_name(name, subfont)?, (i - min + offset) as usize);
+    }
+    bail!("{}: no {:?} in font", name, c)
+}
+
+// subfont_name returns the name of the file holding subfont of the font
+// named font, preferring the deepest grey version of it, like libdraw.
+fn subfont_name(font: &str, subfont: &str) -> Result<String> {
+    let name = match Path::new(font).parent() {
+        Some(dir) if !subfont.starts_with('/') => dir.join(subfont).to_string_lossy().to_string(),
+        _ => subfont.to_string(),
+    };
+    if !name.starts_with("/mnt/font/") {
+        for depth in (0..4).rev() {
+            let grey = format!("{}.{}", name, depth);
+            if read(&grey).is_ok() {
+                return Ok(grey);
+            }
+        }
+    }
+    Ok(name)
+}
+
+// subfont_width returns the width of the ith character of the subfont in
+// the file name: an image followed by a header and the characters' info.
+fn subfont_width(name: &str, i: usize) -> Result<u32> {
+    let data = read(name)?;
+    let info = skip_image(&data)?;
+    if info.len() < 3 * 12 {
+        bail!("{}: short subfont", name);
+    }
+    let n = parse_int(std::str::from_utf8(&info[..12])?.trim())? as usize;
+    let fontchar = &info[3 * 12..];
+    if i >= n || fontchar.len() < (n + 1) * 6 {
+        bail!("{}: no character {} in subfont", name, i);
+    }
+    // Each character is x (2 bytes), top, bottom, left and width.
+    Ok(fontchar[i * 6 + 5] as u32)
+}
+
+// skip_image returns what follows the image at the start of data.
+fn skip_image(data: &[u8]) -> Result<&[u8]> {
+    let (compressed, data) = match data.strip_prefix(b"compressed\n") {
+        Some(data) => (true, data),
+        None => (false, data),
+    };
+    if data.len() < 5 * 12 {
+        bail!("short image");
+    }
+    let hdr: Vec<&str> = std::str::from_utf8(&data[..5 * 12])?
+        .split_whitespace()
+        .collect();
+    if hdr.len() != 5 {
+        bail!("bad image header");
+    }
+    // Old images give log2 of the depth instead of a channel descriptor.
+    let depth = match hdr[0].parse::<u32>() {
+        Ok(ldepth) => 1i64 << ldepth,
+        Err(_) => hdr[0].chars().filter_map(|c| c.to_digit(10)).sum::<u32>() as i64,
+    };
+    let r: Vec<i64> = hdr[1..]
+        .iter()
+        .map(|s| parse_int(s))
+        .collect::<Result<_>>()?;
+    let mut data = &data[5 * 12..];
+    if compressed {
+        // Blocks of lines up to a y, each with a header of that y and the
+        // block's length.
+        let mut y = r[1];
+        while y < r[3] {
+            if data.len() < 2 * 12 {
+                bail!("short compressed image");
+            }
+            let block: Vec<i64> = std::str::from_utf8(&data[..2 * 12])?
+                .split_whitespace()
+                .map(parse_int)
+                .collect::<Result<_>>()?;
+            if block.len() != 2 || block[0] <= y {
+                bail!("bad compressed image");
+            }
+            y = block[0];
+            data = data
+                .get(2 * 12 + block[1] as usize..)
+                .ok_or_else(|| anyhow::anyhow!("short compressed image"))?;
+        }
+        return Ok(data);
+    }
+    let line = if r[0] >= 0 {
+        (r[2] * depth + 7) / 8 - r[0] * depth / 8
+    } else {
+        (-r[0] * depth + 7) / 8 + (r[2] * depth + 7) / 8
+    };
+    match data.get((line * (r[3] - r[1])) as usize..) {
+        Some(rest) => Ok(rest),
+        None => bail!("short image"),
+    }
+}
+
+// parse_int parses a number the way strtol does with base 0.
+fn parse_int(s: &str) -> Result<i64> {
+    let (neg, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s),
+    };
+    let n = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16)?
+    } else if s.len() > 1 && s.starts_with('0') {
+        i64::from_str_radix(&s[1..], 8)?
+    } else {
+        s.parse()?
+    };
+    Ok(if neg { -n } else { n })
+}
+
+// read returns the contents of the font file name. Fonts under /mnt/font
+// are served by fontsrv, and /lib/font/bit is $PLAN9/font, as in
+// plan9port's libdraw.
+fn read(name: &str) -> Result<Vec<u8>> {
+    let mut data = vec![];
+    if let Some(name) = name.strip_prefix("/mnt/font/") {
+        dial::mount_service("font")?
+            .open(name, OpenMode::READ)?
+            .read_to_end(&mut data)?;
+    } else if let Some(name) = name.strip_prefix("/lib/font/bit/") {
+        let root = env::var("PLAN9").unwrap_or_else(|_| "/usr/local/plan9".to_string());
+        data = std::fs::read(Path::new(&root).join("font").join(name))?;
+    } else {
+        data = std::fs::read(name)?;
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::font::*;
+
+    // subfont returns a subfont of characters with widths, optionally
+    // compressing its image.
+    fn subfont(widths: &[u8], compressed: bool) -> Vec<u8> {
+        let mut data = vec![];
+        if compressed {
+            data.extend(b"compressed\n");
+        }
+        for f in &["k1", "0", "0", "16", "2"] {
+            data.extend(format!("{:>11} ", f).bytes());
+        }
+        if compressed {
+            data.extend(format!("{:>11} {:>11} ", 2, 3).bytes());
+            data.extend(&[0x80, 0, 0]);
+        } else {
+            data.extend(&[0; 4]);
+        }
+        for f in &[widths.len(), 2, 2] {
+            data.extend(format!("{:>11} ", f).bytes());
+        }
+        for (i, w) in widths.iter().chain(&[0]).enumerate() {
+            data.extend(&[i as u8, 0, 0, 2, 0, *w]);
+        }
+        data
+    }
+
+    #[test]
+    fn widths() {
+        let dir = std::env::temp_dir().join(format!("plan9-font-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("digits"), subfont(&[5, 7], false)).unwrap();
+        // The deepest grey version of a subfont is used.
+        std::fs::write(dir.join("letters"), b"").unwrap();
+        std::fs::write(dir.join("letters.2"), subfont(&[6, 8], true)).unwrap();
+        let font = dir.join("font");
+        std::fs::write(&font, "2 2\n0x30 0x31 digits\n0x41 0x41 1 letters\n").unwrap();
+        let font = font.to_str().unwrap();
+        assert_eq!(char_width(font, '0').unwrap(), 5);
+        assert_eq!(char_width(font, '1').unwrap(), 7);
+        assert_eq!(char_width(font, 'A').unwrap(), 8);
+        assert_eq!(char_width(&format!("2*{}", font), '0').unwrap(), 10);
+        assert!(char_width(font, 'B').is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ints() {
+        assert_eq!(parse_int("0x7F").unwrap(), 127);
+        assert_eq!(parse_int("010").unwrap(), 8);
+        assert_eq!(parse_int("-12").unwrap(), -12);
+        assert_eq!(parse_int("0").unwrap(), 0);
+        assert!(parse_int("x").is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use anyhow::Result;
 use nine::p2000::OpenMode;
 
-use crate::fid::Fid;
+use crate::{dir::Dir, fid::Fid};
 
 pub struct Fsys {
     pub fid: Fid,
@@ -13,4 +13,7 @@ impl Fsys {
         fid.open(mode)?;
         Ok(fid)
     }
+    pub fn stat(&mut self, name: &str) -> Result<Dir> {
+        self.fid.walk(name)?.stat()
+    }
 }
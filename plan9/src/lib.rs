@@ -1,6 +1,12 @@
 pub mod acme;
 pub mod conn;
 pub mod dial;
+pub mod dir;
+// fakeacme is an acme for tests, built with the fake feature.
+#[cfg(any(test, feature = "fake"))]
+pub mod fakeacme;
 pub mod fid;
+pub mod font;
 pub mod fsys;
 pub mod plumb;
+pub mod srv;
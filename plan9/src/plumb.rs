@@ -13,7 +13,7 @@ lazy_static! {
 }
 
 pub fn open(name: &str, mode: OpenMode) -> Result<Fid> {
-    FSYS.lock().unwrap().open(&name, mode)
+    FSYS.lock().unwrap().open(name, mode)
 }
 
 pub struct Message {
@@ -25,14 +25,14 @@ pub struct Message {
 impl Message {
     pub fn send(self, mut f: Fid) -> Result<()> {
         let mut s: Vec<u8> = vec![];
-        write!(&mut s, "\n")?; // src
-        write!(&mut s, "{}\n", self.dst)?;
-        write!(&mut s, "\n")?; // dir
-        write!(&mut s, "{}\n", self.typ)?;
-        write!(&mut s, "\n")?; // attr
-        write!(&mut s, "{}\n", self.data.len())?;
+        writeln!(&mut s)?; // src
+        writeln!(&mut s, "{}", self.dst)?;
+        writeln!(&mut s)?; // dir
+        writeln!(&mut s, "{}", self.typ)?;
+        writeln!(&mut s)?; // attr
+        writeln!(&mut s, "{}", self.data.len())?;
         s.extend(&self.data);
-        f.write(&s)?;
+        f.write_all(&s)?;
         Ok(())
     }
 }
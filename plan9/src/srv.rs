@@ -0,0 +1,627 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{bail, Result};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use nine::{de::*, p2000::*, ser::*};
+
+use crate::dial;
+
+const IOHDRSZ: u32 = 24;
+const MSIZE: u32 = 8192 + IOHDRSZ;
+
+// Srv is a file tree served over 9P. Each fid holds a File, which is
+// created by attach or walk and handed back to the other methods. Errors
+// are returned to the client as their message.
+pub trait Srv: Send + Sync + 'static {
+    type File: Clone + Send + 'static;
+
+    fn attach(&self, uname: &str, aname: &str) -> Result<Self::File>;
+    // walk returns the file called name in the directory dir.
+    fn walk(&self, dir: &Self::File, name: &str) -> Result<Self::File>;
+    fn open(&self, _file: &mut Self::File, _mode: OpenMode) -> Result<()> {
+        Ok(())
+    }
+    // read returns at most count bytes at offset. It runs on its own thread,
+    // so it may wait for data to become available, and is given a copy of
+    // the fid's file, so any state it changes must be shared between copies.
+    // Directories are read with read_dir instead.
+    fn read(&self, file: &Self::File, offset: u64, count: u32) -> Result<Vec<u8>>;
+    fn read_dir(&self, _dir: &Self::File) -> Result<Vec<Stat>> {
+        Ok(vec![])
+    }
+    // write returns the number of bytes written.
+    fn write(&self, _file: &mut Self::File, _offset: u64, _data: &[u8]) -> Result<u32> {
+        bail!("permission denied")
+    }
+    fn stat(&self, file: &Self::File) -> Result<Stat>;
+    fn clunk(&self, _file: Self::File) {}
+    // flush is called when the client abandons the read with tag on file,
+    // which is still in progress. It should make the read return soon; its
+    // response is dropped.
+    fn flush(&self, _tag: u16, _file: &Self::File) {}
+}
+
+// serve serves srv on stream until the client hangs up.
+pub fn serve<S: Srv>(srv: Arc<S>, stream: UnixStream) -> Result<()> {
+    let reader = stream.try_clone()?;
+    let mut s = Session {
+        srv,
+        reader,
+        writer: Arc::new(Mutex::new(stream)),
+        fids: Arc::new(Mutex::new(HashMap::new())),
+        tags: Arc::new(Mutex::new(Tags {
+            pending: HashMap::new(),
+            flushed: HashSet::new(),
+        })),
+    };
+    s.serve()
+}
+
+// Listener accepts connections to a service posted in the name space
+// directory. Dropping it stops accepting and removes the service.
+pub struct Listener {
+    path: PathBuf,
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+// post serves srv to clients that dial service.
+pub fn post<S: Srv>(srv: Arc<S>, service: &str) -> Result<Listener> {
+    post_in(srv, Path::new(&dial::namespace()), service)
+}
+
+// post_in is post with the name space directory dir.
+pub fn post_in<S: Srv>(srv: Arc<S>, dir: &Path, service: &str) -> Result<Listener> {
+    if !dir.exists() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+    }
+    let path = dir.join(service);
+    // Remove a socket left behind by a server that has gone away.
+    if path.exists() && UnixStream::connect(&path).is_err() {
+        fs::remove_file(&path)?;
+    }
+    let listener = UnixListener::bind(&path)?;
+    let done = Arc::new(AtomicBool::new(false));
+    let stop = Arc::clone(&done);
+    let thread = thread::Builder::new()
+        .name(format!("srv {}", service))
+        .spawn(move || {
+            for stream in listener.incoming() {
+                if stop.load(Ordering::SeqCst) {
+                    return;
+                }
+                let stream = match stream {
+                    Ok(s) => s,
+                    Err(_) => return,
+                };
+                let srv = Arc::clone(&srv);
+                thread::spawn(move || serve(srv, stream));
+            }
+        })?;
+    Ok(Listener {
+        path,
+        done,
+        thread: Some(thread),
+    })
+}
+
+impl Listener {
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        // Wake the accept loop so it sees done and closes the socket.
+        self.done.store(true, Ordering::SeqCst);
+        if UnixStream::connect(&self.path).is_ok() {
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+type Fids<F> = Arc<Mutex<HashMap<u32, Arc<Mutex<F>>>>>;
+
+// Tags tracks the reads in progress, which are the only requests answered
+// out of order.
+struct Tags<F> {
+    // Tag -> file of each read in progress.
+    pending: HashMap<u16, F>,
+    // Tags of flushed reads, whose responses are dropped.
+    flushed: HashSet<u16>,
+}
+
+struct Session<S: Srv> {
+    srv: Arc<S>,
+    reader: UnixStream,
+    writer: Arc<Mutex<UnixStream>>,
+    fids: Fids<S::File>,
+    tags: Arc<Mutex<Tags<S::File>>>,
+}
+
+impl<S: Srv> Session<S> {
+    fn serve(&mut self) -> Result<()> {
+        loop {
+            let size = match self.reader.read_u32::<LittleEndian>() {
+                Ok(n) => n,
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            };
+            if size < 7 {
+                bail!("short 9P message");
+            }
+            let mtype = self.reader.read_u8()?;
+            let mut data = vec![0u8; size as usize - 5];
+            self.reader.read_exact(&mut data)?;
+            let tag = u16::from_le_bytes([data[0], data[1]]);
+            match mtype {
+                100 => {
+                    let tx: Tversion = from_bytes(&data)?;
+                    if !tx.version.starts_with("9P2000") {
+                        self.respond(&Rversion {
+                            tag,
+                            msize: tx.msize,
+                            version: "unknown".into(),
+                        })?;
+                        continue;
+                    }
+                    // A new session starts with no fids.
+                    let fids: Vec<_> = self.fids.lock().unwrap().drain().collect();
+                    for (_, f) in fids {
+                        let f = f.lock().unwrap().clone();
+                        self.srv.clunk(f);
+                    }
+                    self.respond(&Rversion {
+                        tag,
+                        msize: tx.msize.min(MSIZE),
+                        version: "9P2000".into(),
+                    })?
+                }
+                104 => {
+                    let tx: Tattach = from_bytes(&data)?;
+                    let r = self.attach(&tx);
+                    self.reply(tag, r)?
+                }
+                108 => {
+                    let tx: Tflush = from_bytes(&data)?;
+                    // Requests already answered, or never made, have
+                    // nothing to flush, and their tags may be reused.
+                    let file = {
+                        let mut tags = self.tags.lock().unwrap();
+                        let file = tags.pending.get(&tx.oldtag).cloned();
+                        if file.is_some() {
+                            tags.flushed.insert(tx.oldtag);
+                        }
+                        file
+                    };
+                    if let Some(file) = file {
+                        self.srv.flush(tx.oldtag, &file);
+                    }
+                    self.respond(&Rflush { tag })?
+                }
+                110 => {
+                    let tx: Twalk = from_bytes(&data)?;
+                    let r = self.walk(&tx);
+                    self.reply(tag, r)?
+                }
+                112 => {
+                    let tx: Topen = from_bytes(&data)?;
+                    let r = self.open(&tx);
+                    self.reply(tag, r)?
+                }
+                116 => {
+                    let tx: Tread = from_bytes(&data)?;
+                    let file = match self.fid(tx.fid) {
+                        Ok(f) => f.lock().unwrap().clone(),
+                        Err(err) => {
+                            self.reply::<Rread>(tag, Err(err))?;
+                            continue;
+                        }
+                    };
+                    self.tags.lock().unwrap().pending.insert(tag, file.clone());
+                    let s = self.clone_handles()?;
+                    thread::spawn(move || {
+                        let r = s.read(&tx, &file);
+                        let _ = s.reply(tag, r);
+                    });
+                }
+                118 => {
+                    let tx: Twrite = from_bytes(&data)?;
+                    let r = self.write(&tx);
+                    self.reply(tag, r)?
+                }
+                120 => {
+                    let tx: Tclunk = from_bytes(&data)?;
+                    let r = match self.fids.lock().unwrap().remove(&tx.fid) {
+                        Some(f) => {
+                            let f = f.lock().unwrap().clone();
+                            self.srv.clunk(f);
+                            Ok(Rclunk { tag })
+                        }
+                        None => Err(anyhow::anyhow!("unknown fid")),
+                    };
+                    self.reply(tag, r)?
+                }
+                124 => {
+                    let tx: Tstat = from_bytes(&data)?;
+                    let r = self.fid(tx.fid).and_then(|f| {
+                        Ok(Rstat {
+                            tag,
+                            stat: self.srv.stat(&f.lock().unwrap())?,
+                        })
+                    });
+                    self.reply(tag, r)?
+                }
+                _ => self.error(tag, "operation not supported")?,
+            }
+        }
+        Ok(())
+    }
+
+    fn clone_handles(&self) -> Result<Session<S>> {
+        Ok(Session {
+            srv: Arc::clone(&self.srv),
+            reader: self.reader.try_clone()?,
+            writer: Arc::clone(&self.writer),
+            fids: Arc::clone(&self.fids),
+            tags: Arc::clone(&self.tags),
+        })
+    }
+
+    fn fid(&self, fid: u32) -> Result<Arc<Mutex<S::File>>> {
+        match self.fids.lock().unwrap().get(&fid) {
+            Some(f) => Ok(Arc::clone(f)),
+            None => bail!("unknown fid"),
+        }
+    }
+
+    fn attach(&self, tx: &Tattach) -> Result<Rattach> {
+        if self.fids.lock().unwrap().contains_key(&tx.fid) {
+            bail!("fid in use");
+        }
+        let f = self.srv.attach(&tx.uname, &tx.aname)?;
+        let qid = self.srv.stat(&f)?.qid;
+        self.fids
+            .lock()
+            .unwrap()
+            .insert(tx.fid, Arc::new(Mutex::new(f)));
+        Ok(Rattach { tag: tx.tag, qid })
+    }
+
+    fn walk(&self, tx: &Twalk) -> Result<Rwalk> {
+        let mut f = self.fid(tx.fid)?.lock().unwrap().clone();
+        if tx.newfid != tx.fid && self.fids.lock().unwrap().contains_key(&tx.newfid) {
+            bail!("fid in use");
+        }
+        let mut wqid = vec![];
+        for name in &tx.wname {
+            f = match self.srv.walk(&f, name) {
+                Ok(f) => f,
+                Err(err) if wqid.is_empty() => return Err(err),
+                // A partial walk doesn't create newfid.
+                Err(_) => return Ok(Rwalk { tag: tx.tag, wqid }),
+            };
+            wqid.push(self.srv.stat(&f)?.qid);
+        }
+        self.fids
+            .lock()
+            .unwrap()
+            .insert(tx.newfid, Arc::new(Mutex::new(f)));
+        Ok(Rwalk { tag: tx.tag, wqid })
+    }
+
+    fn open(&self, tx: &Topen) -> Result<Ropen> {
+        let f = self.fid(tx.fid)?;
+        let mut f = f.lock().unwrap();
+        self.srv.open(&mut f, tx.mode)?;
+        Ok(Ropen {
+            tag: tx.tag,
+            qid: self.srv.stat(&f)?.qid,
+            iounit: MSIZE - IOHDRSZ,
+        })
+    }
+
+    // read reads from f, a copy of the fid's file, so that the fid is not
+    // locked while the read waits.
+    fn read(&self, tx: &Tread, f: &S::File) -> Result<Rread> {
+        let count = tx.count.min(MSIZE - IOHDRSZ);
+        let stat = self.srv.stat(f)?;
+        if !stat.qid.file_type.contains(FileType::DIR) {
+            let data = self.srv.read(f, tx.offset, count)?;
+            return Ok(Rread { tag: tx.tag, data });
+        }
+        // Return the whole entries that fit, starting at the byte offset
+        // into the concatenated entries.
+        let mut data = vec![];
+        let mut off = 0;
+        for stat in self.srv.read_dir(f)? {
+            let entry = into_bytes(&stat)?;
+            if off >= tx.offset {
+                if data.len() + entry.len() > count as usize {
+                    break;
+                }
+                data.extend(entry.iter());
+            }
+            off += entry.len() as u64;
+        }
+        Ok(Rread { tag: tx.tag, data })
+    }
+
+    fn write(&self, tx: &Twrite) -> Result<Rwrite> {
+        let f = self.fid(tx.fid)?;
+        let mut f = f.lock().unwrap();
+        let count = self.srv.write(&mut f, tx.offset, &tx.data)?;
+        Ok(Rwrite { tag: tx.tag, count })
+    }
+
+    fn reply<T: Serialize + MessageTypeId + Debug>(&self, tag: u16, r: Result<T>) -> Result<()> {
+        // Hold tags until the reply is written so that a flush can't be
+        // answered before it.
+        let mut tags = self.tags.lock().unwrap();
+        tags.pending.remove(&tag);
+        if tags.flushed.remove(&tag) {
+            return Ok(());
+        }
+        match r {
+            Ok(msg) => self.respond(&msg),
+            Err(err) => self.error(tag, &err.to_string()),
+        }
+    }
+
+    fn error(&self, tag: u16, ename: &str) -> Result<()> {
+        self.respond(&Rerror {
+            tag,
+            ename: ename.to_string().into(),
+        })
+    }
+
+    fn respond<T: Serialize + MessageTypeId + Debug>(&self, msg: &T) -> Result<()> {
+        let mut buf = vec![];
+        let n = into_vec(msg, &mut buf)?;
+        let mut w = self.writer.lock().unwrap();
+        w.write_u32::<LittleEndian>(n + 5)?;
+        w.write_u8(<T as MessageTypeId>::MSG_TYPE_ID)?;
+        w.write_all(&buf[..n as usize])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use crate::conn::Conn;
+    use crate::srv::*;
+
+    // Notes is a directory of files that can be read and appended to.
+    struct Notes {
+        files: Mutex<Vec<(String, String)>>,
+        // How many fids have been clunked.
+        clunks: Mutex<usize>,
+    }
+
+    impl Notes {
+        fn new() -> Arc<Notes> {
+            Arc::new(Notes {
+                files: Mutex::new(vec![
+                    ("hello".to_string(), "hello, world\n".to_string()),
+                    ("todo".to_string(), "".to_string()),
+                ]),
+                clunks: Mutex::new(0),
+            })
+        }
+    }
+
+    impl Srv for Notes {
+        // The index of the file, or None for the root directory.
+        type File = Option<usize>;
+
+        fn attach(&self, _uname: &str, _aname: &str) -> Result<Option<usize>> {
+            Ok(None)
+        }
+        fn walk(&self, dir: &Option<usize>, name: &str) -> Result<Option<usize>> {
+            let files = self.files.lock().unwrap();
+            match (dir, files.iter().position(|(n, _)| n == name)) {
+                (None, Some(i)) => Ok(Some(i)),
+                _ => bail!("file does not exist"),
+            }
+        }
+        fn read(&self, file: &Option<usize>, offset: u64, count: u32) -> Result<Vec<u8>> {
+            let files = self.files.lock().unwrap();
+            let data = files[file.unwrap()].1.as_bytes();
+            let off = (offset as usize).min(data.len());
+            let end = (off + count as usize).min(data.len());
+            Ok(data[off..end].to_vec())
+        }
+        fn read_dir(&self, _dir: &Option<usize>) -> Result<Vec<Stat>> {
+            let n = self.files.lock().unwrap().len();
+            (0..n).map(|i| self.stat(&Some(i))).collect()
+        }
+        fn write(&self, file: &mut Option<usize>, _offset: u64, data: &[u8]) -> Result<u32> {
+            let mut files = self.files.lock().unwrap();
+            files[file.unwrap()].1.push_str(std::str::from_utf8(data)?);
+            Ok(data.len() as u32)
+        }
+        fn clunk(&self, _file: Option<usize>) {
+            *self.clunks.lock().unwrap() += 1;
+        }
+        fn stat(&self, file: &Option<usize>) -> Result<Stat> {
+            let files = self.files.lock().unwrap();
+            let (name, length, mode, file_type) = match file {
+                None => (
+                    "/",
+                    0,
+                    FileMode::DIR | FileMode::from_bits_truncate(0o555),
+                    FileType::DIR,
+                ),
+                Some(i) => (
+                    files[*i].0.as_str(),
+                    files[*i].1.len() as u64,
+                    FileMode::from_bits_truncate(0o644),
+                    FileType::FILE,
+                ),
+            };
+            Ok(Stat {
+                type_: 0,
+                dev: 0,
+                qid: Qid {
+                    file_type,
+                    version: 0,
+                    path: file.map_or(0, |i| i as u64 + 1),
+                },
+                mode,
+                atime: 0,
+                mtime: 0,
+                length,
+                name: name.to_string().into(),
+                uid: "glenda".into(),
+                gid: "glenda".into(),
+                muid: "".into(),
+            })
+        }
+    }
+
+    #[test]
+    fn serve() {
+        let (client, server) = UnixStream::pair().unwrap();
+        thread::spawn(move || crate::srv::serve(Notes::new(), server));
+        let mut fsys = Conn::new(client)
+            .unwrap()
+            .attach("glenda".to_string(), "".to_string())
+            .unwrap();
+
+        let mut s = String::new();
+        let mut f = fsys.open("hello", OpenMode::READ).unwrap();
+        f.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "hello, world\n");
+
+        let mut f = fsys.open("todo", OpenMode::WRITE).unwrap();
+        f.write_all(b"write tests\n").unwrap();
+        assert_eq!(fsys.stat("todo").unwrap().length, 12);
+
+        let mut root = fsys.open(".", OpenMode::READ).unwrap();
+        let names: Vec<String> = root
+            .read_dir()
+            .unwrap()
+            .into_iter()
+            .map(|d| d.name)
+            .collect();
+        assert_eq!(names, vec!["hello", "todo"]);
+
+        assert!(fsys.open("missing", OpenMode::READ).is_err());
+        assert!(fsys.open("hello/world", OpenMode::READ).is_err());
+    }
+
+    // rpc sends msg on stream and returns the type and body of the reply.
+    fn rpc<T: Serialize + MessageTypeId + Debug>(
+        stream: &mut UnixStream,
+        msg: &T,
+    ) -> (u8, Vec<u8>) {
+        let mut buf = vec![];
+        let n = into_vec(msg, &mut buf).unwrap();
+        stream.write_u32::<LittleEndian>(n + 5).unwrap();
+        stream.write_u8(<T as MessageTypeId>::MSG_TYPE_ID).unwrap();
+        stream.write_all(&buf[..n as usize]).unwrap();
+        let size = stream.read_u32::<LittleEndian>().unwrap();
+        let mtype = stream.read_u8().unwrap();
+        let mut data = vec![0u8; size as usize - 5];
+        stream.read_exact(&mut data).unwrap();
+        (mtype, data)
+    }
+
+    #[test]
+    fn flush() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let notes = Notes::new();
+        let srv = Arc::clone(&notes);
+        thread::spawn(move || crate::srv::serve(srv, server));
+        let c = &mut client;
+        let version = Tversion {
+            tag: NOTAG,
+            msize: MSIZE,
+            version: "9P2000".into(),
+        };
+        rpc(c, &version);
+        rpc(
+            c,
+            &Tattach {
+                tag: 1,
+                fid: 0,
+                afid: !0,
+                uname: "glenda".into(),
+                aname: "".into(),
+            },
+        );
+        rpc(
+            c,
+            &Twalk {
+                tag: 1,
+                fid: 0,
+                newfid: 1,
+                wname: vec!["hello".into()],
+            },
+        );
+        rpc(
+            c,
+            &Topen {
+                tag: 1,
+                fid: 1,
+                mode: OpenMode::READ,
+            },
+        );
+        let read = Tread {
+            tag: 1,
+            fid: 1,
+            offset: 0,
+            count: 100,
+        };
+        assert_eq!(rpc(c, &read).0, Rread::MSG_TYPE_ID);
+        // Flushing the answered read must not drop the reply to the next
+        // request using its tag.
+        let (mtype, _) = rpc(c, &Tflush { tag: 2, oldtag: 1 });
+        assert_eq!(mtype, Rflush::MSG_TYPE_ID);
+        let (mtype, data) = rpc(c, &read);
+        assert_eq!(mtype, Rread::MSG_TYPE_ID);
+        let r: Rread = from_bytes(&data).unwrap();
+        assert_eq!(r.data, b"hello, world\n");
+
+        // A new version clunks the fids of the old session.
+        rpc(c, &version);
+        assert_eq!(*notes.clunks.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn post() {
+        let dir = std::env::temp_dir().join(format!("srvtest.{}", std::process::id()));
+        let l = crate::srv::post_in(Notes::new(), &dir, "notes").unwrap();
+        assert!(l.path().exists());
+        let mut fsys = dial::dial(l.path().to_str().unwrap())
+            .unwrap()
+            .attach("glenda".to_string(), "".to_string())
+            .unwrap();
+        let mut s = String::new();
+        let mut f = fsys.open("hello", OpenMode::READ).unwrap();
+        f.read_to_string(&mut s).unwrap();
+        assert_eq!(s, "hello, world\n");
+        let path = l.path().clone();
+        drop(l);
+        assert!(!path.exists());
+        let _ = fs::remove_dir(&dir);
+    }
+}
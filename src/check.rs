@@ -0,0 +1,195 @@
+// acre check: reports problems with acre.toml and with the services and
+// programs acre needs, without starting anything.
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use lsp_types::Url;
+use plan9::dial;
+use regex::Regex;
+
+use crate::{expand, TomlConfig};
+
+// The keys a server's table may have.
+const SERVER_KEYS: &[&str] = &[
+    "executable",
+    "args",
+    "env",
+    "cwd",
+    "connect",
+    "files",
+    "root_uri",
+    "workspace_folders",
+    "options",
+    "settings",
+    "actions_on_put",
+    "format_on_put",
+    "root_markers",
+    "primary",
+    "idle_timeout",
+    "trace",
+    "request_timeout",
+    "language_ids",
+    "formatting",
+];
+
+// check prints what it finds about the config at path and the acme and
+// plumber services, returning whether there were problems.
+pub fn check(path: &Path) -> bool {
+    let mut problems = match std::fs::read_to_string(path) {
+        Ok(text) => check_config(&text),
+        Err(err) => vec![err.to_string()],
+    };
+    for p in &mut problems {
+        *p = format!("{}: {}", path.display(), p);
+    }
+    for service in &["acme", "plumb"] {
+        let addr = format!("{}/{}", dial::namespace(), service);
+        match dial::dial(&addr) {
+            Ok(_) => println!("{}: reachable at {}", service, addr),
+            Err(err) => problems.push(format!("{}: not reachable at {}: {}", service, addr, err)),
+        }
+    }
+    for p in &problems {
+        println!("{}", p);
+    }
+    !problems.is_empty()
+}
+
+// check_config returns the problems with the config text.
+fn check_config(text: &str) -> Vec<String> {
+    let value: toml::Value = match toml::from_str(text) {
+        Ok(value) => value,
+        Err(err) => return vec![err.to_string()],
+    };
+    let mut problems = vec![];
+    if let Some(table) = value.as_table() {
+        for key in table.keys().filter(|k| *k != "servers") {
+            problems.push(format!("unknown key {:?}", key));
+        }
+    }
+    if let Some(servers) = value.get("servers").and_then(|s| s.as_table()) {
+        for (name, server) in servers {
+            for key in server.as_table().into_iter().flat_map(|t| t.keys()) {
+                if !SERVER_KEYS.contains(&key.as_str()) {
+                    problems.push(format!("servers.{}: unknown key {:?}", name, key));
+                }
+            }
+        }
+    }
+    let config: TomlConfig = match toml::from_str(text) {
+        Ok(config) => config,
+        Err(err) => {
+            problems.push(err.to_string());
+            return problems;
+        }
+    };
+    let mut names: Vec<&String> = config.servers.keys().collect();
+    names.sort();
+    for name in names {
+        let server = &config.servers[name];
+        let mut problem = |p: String| problems.push(format!("servers.{}: {}", name, p));
+        let regexes = std::iter::once(&server.files)
+            .chain(server.language_ids.iter().flatten().map(|(re, _)| re))
+            .chain(
+                server
+                    .formatting
+                    .iter()
+                    .flat_map(|f| f.files.iter().flatten())
+                    .map(|(re, _)| re),
+            );
+        for re in regexes {
+            if let Err(err) = Regex::new(re) {
+                problem(format!("invalid regex {:?}: {}", re, err));
+            }
+        }
+        let uris = server
+            .root_uri
+            .iter()
+            .chain(server.workspace_folders.iter().flatten());
+        for uri in uris {
+            match Url::parse(uri) {
+                Ok(url) if url.scheme() == "file" => {}
+                Ok(_) => problem(format!("URI {:?} is not a file URI", uri)),
+                Err(err) => problem(format!("malformed URI {:?}: {}", uri, err)),
+            }
+        }
+        if server.connect.is_none() {
+            let executable = expand(server.executable.as_deref().unwrap_or(name));
+            if find_executable(&executable).is_none() {
+                problem(format!("executable {:?} not found in $PATH", executable));
+            }
+        }
+    }
+    problems
+}
+
+// find_executable returns the path of the program name would run.
+fn find_executable(name: &str) -> Option<PathBuf> {
+    let is_executable = |p: &Path| {
+        p.metadata()
+            .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false)
+    };
+    if name.contains('/') {
+        let path = PathBuf::from(name);
+        return Some(path).filter(|p| is_executable(p));
+    }
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(name))
+        .find(|p| is_executable(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::check::*;
+
+    #[test]
+    fn check_config() {
+        let config = r#"
+            server = 1
+            [servers.sh]
+            files = '\.sh$'
+            fils = '\.sh$'
+            root_uri = "/home/user/project"
+            workspace_folders = ["file:///home/user/project", "http://example.com"]
+            [servers.missing]
+            executable = "acre-no-such-server"
+            files = '\.(rs$'
+            language_ids = { '[' = "c" }
+            [servers.remote]
+            connect = "tcp!localhost!4389"
+            files = '\.go$'
+        "#;
+        let problems = crate::check::check_config(config);
+        let problems: Vec<&str> = problems.iter().map(|p| p.lines().next().unwrap()).collect();
+        assert_eq!(
+            problems,
+            vec![
+                r#"unknown key "server""#,
+                r#"servers.sh: unknown key "fils""#,
+                r#"servers.missing: invalid regex "\\.(rs$": regex parse error:"#,
+                r#"servers.missing: invalid regex "[": regex parse error:"#,
+                r#"servers.missing: executable "acre-no-such-server" not found in $PATH"#,
+                r#"servers.sh: malformed URI "/home/user/project": relative URL without a base"#,
+                r#"servers.sh: URI "http://example.com" is not a file URI"#,
+            ]
+        );
+        assert_eq!(
+            crate::check::check_config("[servers.sh]\n"),
+            vec!["missing field `files` for key `servers.sh` at line 1 column 1"]
+        );
+    }
+
+    #[test]
+    fn find_executable() {
+        assert!(crate::check::find_executable("sh").is_some());
+        assert_eq!(
+            crate::check::find_executable("/bin/sh"),
+            Some(PathBuf::from("/bin/sh"))
+        );
+        assert_eq!(crate::check::find_executable("acre-no-such-server"), None);
+        assert_eq!(crate::check::find_executable("/"), None);
+    }
+}
@@ -0,0 +1,219 @@
+// Formatting settings from .editorconfig files; see https://editorconfig.org.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use regex::Regex;
+
+use crate::FormattingConfig;
+
+const EDITORCONFIG: &str = ".editorconfig";
+
+// formatting returns the formatting settings the .editorconfig files above
+// path give it.
+pub fn formatting(path: &Path) -> Result<FormattingConfig> {
+    let props = properties(path)?;
+    let get = |key: &str| props.get(key).map(|v| v.as_str());
+    let flag = |key: &str| match get(key) {
+        Some("true") => Some(true),
+        Some("false") => Some(false),
+        _ => None,
+    };
+    let tab_width = get("tab_width").and_then(|w| w.parse().ok());
+    Ok(FormattingConfig {
+        tab_size: match get("indent_size") {
+            Some("tab") | None => tab_width,
+            Some(size) => size.parse().ok().or(tab_width),
+        },
+        insert_spaces: match get("indent_style") {
+            Some("space") => Some(true),
+            Some("tab") => Some(false),
+            _ => None,
+        },
+        trim_trailing_whitespace: flag("trim_trailing_whitespace"),
+        insert_final_newline: flag("insert_final_newline"),
+        ..Default::default()
+    })
+}
+
+// properties returns the properties, by lowercased name and value, that the
+// .editorconfig files in the directories above path give it. Nearer files
+// and later sections take precedence. Sections with globs that can't be
+// matched are skipped.
+fn properties(path: &Path) -> Result<HashMap<String, String>> {
+    let mut files = vec![];
+    for dir in path.ancestors().skip(1) {
+        let config = dir.join(EDITORCONFIG);
+        if !config.is_file() {
+            continue;
+        }
+        let text = match std::fs::read_to_string(&config) {
+            Ok(text) => text,
+            Err(err) => bail!("{}: {}", config.display(), err),
+        };
+        let (root, sections) = parse(&text);
+        files.push((dir, sections));
+        if root {
+            break;
+        }
+    }
+    let mut props = HashMap::new();
+    for (dir, sections) in files.iter().rev() {
+        let rel = match path.strip_prefix(dir) {
+            Ok(rel) => rel.to_string_lossy(),
+            Err(_) => continue,
+        };
+        for (glob, section) in sections {
+            let re = match Regex::new(&glob_regex(glob)) {
+                Ok(re) => re,
+                Err(_) => continue,
+            };
+            if re.is_match(&rel) {
+                props.extend(section.iter().cloned());
+            }
+        }
+    }
+    Ok(props)
+}
+
+// Section is a glob and the properties it sets.
+type Section = (String, Vec<(String, String)>);
+
+// parse returns whether text is marked as the root .editorconfig, and its
+// sections.
+fn parse(text: &str) -> (bool, Vec<Section>) {
+    let mut root = false;
+    let mut sections: Vec<Section> = vec![];
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(glob) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sections.push((glob.to_string(), vec![]));
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim().to_lowercase(), value.trim().to_lowercase()),
+            None => continue,
+        };
+        match sections.last_mut() {
+            Some((_, props)) => props.push((key, value)),
+            None if key == "root" => root = value == "true",
+            None => {}
+        }
+    }
+    (root, sections)
+}
+
+// glob_regex returns a regex matching the paths, relative to the
+// .editorconfig's directory, that glob matches. Globs without a / match
+// files in any directory.
+fn glob_regex(glob: &str) -> String {
+    let anchored = glob.contains('/');
+    let chars: Vec<char> = glob.strip_prefix('/').unwrap_or(glob).chars().collect();
+    let mut re = String::new();
+    let mut braces = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        match c {
+            '*' if chars.get(i) == Some(&'*') => {
+                i += 1;
+                re.push_str(".*");
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            '[' => match chars[i..].iter().position(|c| *c == ']') {
+                Some(n) => {
+                    let class: String = chars[i..i + n].iter().collect();
+                    i += n + 1;
+                    let (negate, class) = match class.strip_prefix('!') {
+                        Some(class) => ("^", class.to_string()),
+                        None => ("", class),
+                    };
+                    re.push_str(&format!("[{}{}]", negate, class.replace('[', "\\[")));
+                }
+                None => re.push_str("\\["),
+            },
+            '{' => {
+                braces += 1;
+                re.push_str("(?:");
+            }
+            ',' if braces > 0 => re.push('|'),
+            '}' if braces > 0 => {
+                braces -= 1;
+                re.push(')');
+            }
+            '\\' if i < chars.len() => {
+                re.push_str(&regex::escape(&chars[i].to_string()));
+                i += 1;
+            }
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    for _ in 0..braces {
+        re.push(')');
+    }
+    if anchored {
+        format!("^{}$", re)
+    } else {
+        format!("^(?:.*/)?{}$", re)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editorconfig::*;
+
+    #[test]
+    fn glob_regex() {
+        let matches = |glob: &str, path: &str| {
+            Regex::new(&crate::editorconfig::glob_regex(glob))
+                .unwrap()
+                .is_match(path)
+        };
+        assert!(matches("*", "a.py"));
+        assert!(matches("*.py", "src/a.py"));
+        assert!(!matches("*.py", "a.pyc"));
+        assert!(matches("*.{yml,yaml}", "ci/a.yaml"));
+        assert!(matches("/src/*.rs", "src/a.rs"));
+        assert!(!matches("src/*.rs", "src/x/a.rs"));
+        assert!(matches("src/**.rs", "src/x/a.rs"));
+        assert!(matches("Makefile", "x/Makefile"));
+        assert!(matches("[!a]?.c", "bc.c"));
+        assert!(!matches("[!a]?.c", "ac.c"));
+    }
+
+    #[test]
+    fn formatting() {
+        let dir = std::env::temp_dir().join(format!("acre-editorconfig-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("p/src")).unwrap();
+        // Files above the root are ignored.
+        std::fs::write(dir.join(EDITORCONFIG), "[*]\nindent_size = 8\n").unwrap();
+        std::fs::write(
+            dir.join("p").join(EDITORCONFIG),
+            "root = true\n\n[*]\nindent_style = tab\ntab_width = 8\n\n[*.py]\nindent_style = Space\nindent_size = 4\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("p/src").join(EDITORCONFIG),
+            "# Nearer files win.\n[*.py]\nindent_size = 2\ntrim_trailing_whitespace = false\n[]\nindent_size = 3\n",
+        )
+        .unwrap();
+        let f = crate::editorconfig::formatting(&dir.join("p/src/a.py")).unwrap();
+        assert_eq!(f.tab_size, Some(2));
+        assert_eq!(f.insert_spaces, Some(true));
+        assert_eq!(f.trim_trailing_whitespace, Some(false));
+        assert_eq!(f.insert_final_newline, None);
+        let f = crate::editorconfig::formatting(&dir.join("p/src/a.go")).unwrap();
+        assert_eq!(f.tab_size, Some(8));
+        assert_eq!(f.insert_spaces, Some(false));
+        let f = crate::editorconfig::formatting(&dir.join("a.go")).unwrap();
+        assert_eq!(f.tab_size, Some(8));
+        assert_eq!(f.insert_spaces, None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,239 @@
+// A language server that replays a script, for testing acre's LSP handling
+// without a real server.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+use serde_json::{json, Value};
+
+use crate::lsp::{self, Transport};
+
+// Step is one exchange in a script. Steps that wait for a message from the
+// client skip any messages with other methods.
+pub enum Step {
+    // Wait for a request and answer it with a result.
+    Respond(&'static str, Value),
+    // Wait for a request and answer it with an error.
+    Fail(&'static str, &'static str),
+    // Wait for a notification, or for a response to a Request if the method
+    // is "response".
+    Expect(&'static str),
+    // Send a notification.
+    Notify(&'static str, Value),
+    // Send a request from the server.
+    Request(&'static str, Value),
+    // Write bytes as they are, to send malformed frames.
+    Raw(&'static str),
+    // Close the server's output, as if it exited.
+    Close,
+}
+
+// FakeServer is the server side of a client connected by spawn.
+pub struct FakeServer {
+    // The (method, params) of each message received from the client.
+    received: Arc<Mutex<Vec<(String, Value)>>>,
+    done: Receiver<Result<()>>,
+}
+
+// spawn returns a client connected to a server playing script.
+pub fn spawn(name: &str, script: Vec<Step>) -> (lsp::Client, usize, FakeServer) {
+    let (transport, fake) = serve(script);
+    let (c, id) = lsp::Client::new(name.to_string(), transport, None, None, None, None).unwrap();
+    (c, id, fake)
+}
+
+// serve returns a transport to a server playing script.
+pub fn serve(script: Vec<Step>) -> (Transport, FakeServer) {
+    let (r, cw) = pipe();
+    let (cr, w) = pipe();
+    let transport = Transport::Streams(Box::new(cr), Box::new(cw));
+    let received = Arc::new(Mutex::new(vec![]));
+    let (done_s, done) = bounded(1);
+    let mut p = Player {
+        r: BufReader::new(r),
+        w: Some(w),
+        received: Arc::clone(&received),
+        next_id: 1000,
+    };
+    thread::spawn(move || {
+        let _ = done_s.send(p.play(script));
+        // Keep the connection open until the client closes it.
+        while p.record().is_ok() {}
+    });
+    (transport, FakeServer { received, done })
+}
+
+impl FakeServer {
+    // wait waits for the script to finish.
+    pub fn wait(&self) {
+        match self.done.recv_timeout(Duration::from_secs(5)) {
+            Ok(r) => r.unwrap(),
+            Err(_) => panic!(
+                "script did not finish; received {:?}",
+                self.received.lock().unwrap()
+            ),
+        }
+    }
+
+    // received returns the params of the messages received with method.
+    pub fn received(&self, method: &str) -> Vec<Value> {
+        self.received
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(m, _)| m == method)
+            .map(|(_, p)| p.clone())
+            .collect()
+    }
+}
+
+struct Player {
+    r: BufReader<PipeReader>,
+    // None after Close.
+    w: Option<PipeWriter>,
+    received: Arc<Mutex<Vec<(String, Value)>>>,
+    next_id: usize,
+}
+
+impl Player {
+    fn play(&mut self, script: Vec<Step>) -> Result<()> {
+        for step in script {
+            match step {
+                Step::Respond(method, result) => {
+                    let id = self.wait_for(method)?;
+                    self.send(json!({"jsonrpc": "2.0", "id": id, "result": result}))?;
+                }
+                Step::Fail(method, message) => {
+                    let id = self.wait_for(method)?;
+                    self.send(json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": {"code": -32603, "message": message},
+                    }))?;
+                }
+                Step::Expect(method) => {
+                    self.wait_for(method)?;
+                }
+                Step::Notify(method, params) => {
+                    self.send(json!({"jsonrpc": "2.0", "method": method, "params": params}))?;
+                }
+                Step::Request(method, params) => {
+                    let id = self.next_id;
+                    self.next_id += 1;
+                    self.send(json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "method": method,
+                        "params": params,
+                    }))?;
+                }
+                Step::Raw(s) => self.writer()?.write_all(s.as_bytes())?,
+                Step::Close => self.w = None,
+            }
+        }
+        Ok(())
+    }
+
+    // wait_for reads messages until one with method arrives and returns its
+    // id, if any.
+    fn wait_for(&mut self, method: &str) -> Result<Value> {
+        loop {
+            let msg = self.record()?;
+            if msg["method"].as_str().unwrap_or("response") == method {
+                return Ok(msg["id"].clone());
+            }
+        }
+    }
+
+    // record reads a message and adds it to received. Responses are
+    // recorded under the method "response".
+    fn record(&mut self) -> Result<Value> {
+        let msg = self.recv()?;
+        let entry = match msg["method"].as_str() {
+            Some(m) => (m.to_string(), msg["params"].clone()),
+            None => ("response".to_string(), msg.clone()),
+        };
+        self.received.lock().unwrap().push(entry);
+        Ok(msg)
+    }
+
+    fn recv(&mut self) -> Result<Value> {
+        let mut len = 0;
+        loop {
+            let mut line = String::new();
+            if self.r.read_line(&mut line)? == 0 {
+                bail!("client closed connection");
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(n) = line.strip_prefix("Content-Length: ") {
+                len = n.parse()?;
+            }
+        }
+        let mut buf = vec![0; len];
+        self.r.read_exact(&mut buf)?;
+        Ok(serde_json::from_slice(&buf)?)
+    }
+
+    fn writer(&mut self) -> Result<&mut PipeWriter> {
+        match &mut self.w {
+            Some(w) => Ok(w),
+            None => bail!("connection closed"),
+        }
+    }
+
+    fn send(&mut self, msg: Value) -> Result<()> {
+        let s = msg.to_string();
+        write!(self.writer()?, "Content-Length: {}\r\n\r\n{}", s.len(), s)?;
+        Ok(())
+    }
+}
+
+// PipeReader and PipeWriter are the ends of an in-memory pipe.
+struct PipeReader {
+    r: Receiver<Vec<u8>>,
+    buf: Vec<u8>,
+}
+
+struct PipeWriter {
+    s: Sender<Vec<u8>>,
+}
+
+fn pipe() -> (PipeReader, PipeWriter) {
+    let (s, r) = unbounded();
+    (PipeReader { r, buf: vec![] }, PipeWriter { s })
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.buf.is_empty() {
+            // A closed pipe reads as EOF.
+            match self.r.recv() {
+                Ok(data) => self.buf = data,
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.buf.len());
+        buf[..n].copy_from_slice(&self.buf[..n]);
+        self.buf.drain(..n);
+        Ok(n)
+    }
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.s.send(buf.to_vec()) {
+            Ok(_) => Ok(buf.len()),
+            Err(_) => Err(std::io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -1,106 +1,169 @@
+use std::fs::{File, OpenOptions};
 use std::io::{BufRead, BufReader, Read, Write};
-use std::process::{Child, ChildStdin, Command, Stdio};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use crossbeam_channel::{unbounded, Receiver};
 use lsp_types::{notification::*, request::*, *};
-use regex;
-use serde_json;
 
 pub struct Client {
     pub name: String,
-    proc: Child,
-    pub files: regex::Regex,
-    stdin: ChildStdin,
+    proc: Option<Child>,
+    stdin: Box<dyn Write + Send>,
     next_id: usize,
+    pub log: Option<Log>,
 
-    pub msg_r: Receiver<Vec<u8>>,
+    pub msg_r: Receiver<Incoming>,
+}
+
+// Transport is how a Client reaches its language server.
+pub enum Transport {
+    // Stdio runs program and talks to it over its stdin and stdout. env is
+    // added to acre's environment, and cwd is the directory to run it in.
+    Stdio {
+        program: String,
+        args: Vec<String>,
+        env: Vec<(String, String)>,
+        cwd: Option<String>,
+    },
+    // Dial connects to a running server at an address like
+    // tcp!localhost!4389 or unix!/tmp/server.sock.
+    Dial(String),
+    // Streams talks over an already connected reader and writer.
+    #[cfg(test)]
+    Streams(Box<dyn Read + Send>, Box<dyn Write + Send>),
+}
+
+type Conn = (Option<Child>, Box<dyn Read + Send>, Box<dyn Write + Send>);
+
+impl Transport {
+    fn connect(self) -> Result<Conn> {
+        match self {
+            Transport::Stdio {
+                program,
+                args,
+                env,
+                cwd,
+            } => {
+                let mut cmd = Command::new(&program);
+                cmd.args(args).envs(env);
+                if let Some(cwd) = cwd {
+                    cmd.current_dir(cwd);
+                }
+                let mut proc = match cmd
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                {
+                    Ok(proc) => proc,
+                    Err(err) => bail!("could not execute {}: {}", program, err),
+                };
+                let stdout = proc.stdout.take().unwrap();
+                let stdin = proc.stdin.take().unwrap();
+                Ok((Some(proc), Box::new(stdout), Box::new(stdin)))
+            }
+            Transport::Dial(addr) => {
+                let sp: Vec<&str> = addr.split('!').collect();
+                match sp.as_slice() {
+                    ["tcp", host, port] | ["net", host, port] => {
+                        let conn = TcpStream::connect((*host, port.parse::<u16>()?))?;
+                        Ok((None, Box::new(conn.try_clone()?), Box::new(conn)))
+                    }
+                    ["unix", path] => {
+                        let conn = UnixStream::connect(path)?;
+                        Ok((None, Box::new(conn.try_clone()?), Box::new(conn)))
+                    }
+                    _ => bail!("bad address {:?}", addr),
+                }
+            }
+            #[cfg(test)]
+            Transport::Streams(r, w) => Ok((None, r, w)),
+        }
+    }
 }
 
 impl Client {
     #![allow(deprecated)]
-    pub fn new<I, S>(
+    pub fn new(
         name: String,
-        files: String,
-        program: S,
-        args: I,
+        transport: Transport,
+        log: Option<Log>,
         root_uri: Option<String>,
         workspace_folders: Option<Vec<String>>,
         options: Option<serde_json::Value>,
-    ) -> Result<(Client, usize)>
-    where
-        I: IntoIterator<Item = S>,
-        S: AsRef<std::ffi::OsStr> + std::fmt::Display + Clone,
-    {
-        let mut proc = Command::new(program.clone())
-            .args(args)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()
-            .expect(&format!("could not execute: {}", program));
-        let mut stdout = BufReader::new(proc.stdout.take().unwrap());
-        let stdin = proc.stdin.take().unwrap();
+    ) -> Result<(Client, usize)> {
+        let parse =
+            |u: &str| Url::parse(u).map_err(|err| anyhow!("{}: bad URI {:?}: {}", name, u, err));
+        let workspace_folders = match workspace_folders {
+            Some(f) => Some(
+                f.iter()
+                    .map(|x| {
+                        Ok(WorkspaceFolder {
+                            uri: parse(x)?,
+                            name: "".to_string(),
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+            ),
+            None => None,
+        };
+        let root_uri = match root_uri {
+            Some(u) => Some(parse(&u)?),
+            None => None,
+        };
+        let (mut proc, r, w) = transport.connect()?;
+        if let Some(stderr) = proc.as_mut().and_then(|p| p.stderr.take()) {
+            let log = log.clone();
+            let name = name.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(stderr).lines() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(_) => return,
+                    };
+                    match &log {
+                        Some(log) => log.write("stderr", &line),
+                        None => eprintln!("{}: {}", name, line),
+                    }
+                }
+            });
+        }
+        let mut stdout = BufReader::new(r);
         let (msg_s, msg_r) = unbounded();
+        let trace = log.as_ref().map(|l| l.trace);
+        let reader_log = log.clone();
         let mut c = Client {
             name,
-            files: regex::Regex::new(&files)?,
             proc,
-            stdin,
+            stdin: w,
             next_id: 1,
+            log,
             msg_r,
         };
         thread::spawn(move || loop {
-            let mut line = String::new();
-            let mut content_len: usize = 0;
-            loop {
-                line.clear();
-                stdout.read_line(&mut line).unwrap();
-                if line.trim().len() == 0 {
-                    break;
+            let msg = read_message(&mut stdout);
+            if let Some(log) = &reader_log {
+                match &msg {
+                    Incoming::Message(body) => log.message("<--", body),
+                    Incoming::Error(err) => log.write("error", err),
+                    Incoming::Exit => log.write("exit", "server closed its output"),
                 }
-                let sp: Vec<&str> = line.trim().split(": ").collect();
-                if sp.len() < 2 {
-                    panic!("bad line: {}", line);
-                }
-                match sp[0] {
-                    "Content-Length" => {
-                        content_len = sp[1].parse().unwrap();
-                    }
-                    _ => {
-                        panic!("unrecognized header: {}", sp[0]);
-                    }
-                }
-            }
-            if content_len == 0 {
-                panic!("expected content-length");
             }
-            let mut v = vec![0u8; content_len];
-            stdout.read_exact(&mut v).unwrap();
-            if cfg!(debug_assertions) {
-                //println!("got: {}", std::str::from_utf8(&v).unwrap());
+            let exit = matches!(msg, Incoming::Exit);
+            if msg_s.send(msg).is_err() || exit {
+                return;
             }
-            msg_s.send(v).unwrap();
         });
-        // TODO: remove the unwrap here. Unsure how to bubble up errors
-        // from a closure.
-        let workspace_folders: Option<Vec<WorkspaceFolder>> = match workspace_folders {
-            Some(f) => Some(
-                f.iter()
-                    .map(|x| WorkspaceFolder {
-                        uri: Url::parse(x).unwrap(),
-                        name: "".to_string(),
-                    })
-                    .collect(),
-            ),
-            None => None,
-        };
-        let root_uri = match root_uri {
-            Some(u) => Some(Url::parse(&u)?),
-            None => None,
-        };
         let id = c.send::<Initialize>(InitializeParams {
-            process_id: Some(1),
+            // Servers exit on their own if acre dies.
+            process_id: Some(std::process::id()),
             root_path: None,
             root_uri,
             initialization_options: options,
@@ -111,9 +174,14 @@ impl Client {
                     show_message: None,
                     show_document: None,
                 }),
+                workspace: Some(WorkspaceClientCapabilities {
+                    workspace_folders: Some(true),
+                    configuration: Some(true),
+                    ..Default::default()
+                }),
                 ..Default::default()
             },
-            trace: None,
+            trace,
             workspace_folders,
             client_info: None,
             locale: None,
@@ -128,12 +196,7 @@ impl Client {
             method: R::METHOD,
             params,
         };
-        let s = serde_json::to_string(&msg)?;
-        if cfg!(debug_assertions) {
-            //println!("send request: {}", s);
-        }
-        let s = format!("Content-Length: {}\r\n\r\n{}", s.len(), s);
-        write!(self.stdin, "{}", s)?;
+        self.write(&serde_json::to_string(&msg)?)?;
         Ok(id)
     }
     pub fn notify<N: Notification>(&mut self, params: N::Params) -> Result<()> {
@@ -142,14 +205,75 @@ impl Client {
             method: N::METHOD,
             params,
         };
-        let s = serde_json::to_string(&msg)?;
-        if cfg!(debug_assertions) {
-            //println!("send notification: {}", msg.method);
+        self.write(&serde_json::to_string(&msg)?)
+    }
+    // respond answers the server's request id with result.
+    pub fn respond<R: Request>(&mut self, id: usize, result: R::Result) -> Result<()> {
+        let msg = ResponseMessage {
+            jsonrpc: "2.0",
+            id,
+            result,
+        };
+        self.write(&serde_json::to_string(&msg)?)
+    }
+    // respond_error answers the server's request id with an error.
+    pub fn respond_error(&mut self, id: usize, code: i64, message: String) -> Result<()> {
+        let msg = ErrorMessage {
+            jsonrpc: "2.0",
+            id,
+            error: ResponseError {
+                code,
+                message,
+                data: None,
+            },
+        };
+        self.write(&serde_json::to_string(&msg)?)
+    }
+    fn write(&mut self, s: &str) -> Result<()> {
+        if let Some(log) = &self.log {
+            log.message("-->", s.as_bytes());
         }
-        let s = format!("Content-Length: {}\r\n\r\n{}", s.len(), s);
-        write!(self.stdin, "{}", s)?;
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{}", s.len(), s)?;
         Ok(())
     }
+    // shutdown asks the server to shut down and exit, waiting up to timeout
+    // for each. The server is killed if it doesn't.
+    pub fn shutdown(mut self, timeout: Duration) -> Result<()> {
+        let id = self.send::<Shutdown>(())?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.msg_r.recv_timeout(left) {
+                Ok(Incoming::Message(msg)) => {
+                    let msg: DeMessage = match serde_json::from_slice(&msg) {
+                        Ok(msg) => msg,
+                        Err(_) => continue,
+                    };
+                    if msg.id == Some(id) && msg.method.is_none() {
+                        break;
+                    }
+                }
+                Ok(Incoming::Error(_)) => {}
+                // The server is already gone.
+                Ok(Incoming::Exit) => return Ok(()),
+                Err(_) => bail!("{} did not shut down in time", self.name),
+            }
+        }
+        self.notify::<Exit>(())?;
+        let proc = match &mut self.proc {
+            Some(proc) => proc,
+            None => return Ok(()),
+        };
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if proc.try_wait()?.is_some() {
+                self.proc = None;
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        bail!("{} did not exit in time", self.name)
+    }
     fn new_id(&mut self) -> Result<usize> {
         let id = self.next_id;
         self.next_id += 1;
@@ -159,8 +283,197 @@ impl Client {
 
 impl Drop for Client {
     fn drop(&mut self) {
-        let _ = self.proc.kill();
+        if let Some(proc) = &mut self.proc {
+            let _ = proc.kill();
+            let _ = proc.wait();
+        }
+    }
+}
+
+// Log is a server's log file. Its stderr is always written there, and
+// messages to and from it are too if trace is on.
+#[derive(Clone)]
+pub struct Log {
+    file: Arc<Mutex<File>>,
+    pub trace: TraceOption,
+}
+
+impl Log {
+    // open opens the log at path for appending, creating its directory.
+    pub fn open(path: &Path, trace: TraceOption) -> Result<Log> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Log {
+            file: Arc::new(Mutex::new(file)),
+            trace,
+        })
+    }
+    // write adds a timestamped line of text to the log, after kind.
+    pub fn write(&self, kind: &str, text: &str) {
+        let mut file = self.file.lock().unwrap();
+        let _ = writeln!(
+            file,
+            "{} {} {}",
+            timestamp(SystemTime::now()),
+            kind,
+            text.trim_end()
+        );
+    }
+    // message traces a message sent (-->) or received (<--). Only its kind,
+    // method and id are written unless trace is verbose.
+    fn message(&self, dir: &str, body: &[u8]) {
+        match self.trace {
+            TraceOption::Off => {}
+            TraceOption::Messages => self.write(dir, &summary(body)),
+            TraceOption::Verbose => self.write(dir, &String::from_utf8_lossy(body)),
+        }
+    }
+}
+
+// summary describes a message by its kind, method and id.
+fn summary(body: &[u8]) -> String {
+    let v: serde_json::Value = match serde_json::from_slice(body) {
+        Ok(v) => v,
+        Err(_) => return "malformed message".to_string(),
+    };
+    match (v.get("id"), v["method"].as_str()) {
+        (Some(id), Some(method)) => format!("request {} {}", id, method),
+        (None, Some(method)) => format!("notification {}", method),
+        (Some(id), None) => format!("response {}", id),
+        (None, None) => "unknown message".to_string(),
+    }
+}
+
+// timestamp formats t as an RFC 3339 UTC time with milliseconds.
+fn timestamp(t: SystemTime) -> String {
+    let d = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = d.as_secs();
+    // Convert days since the epoch to a civil date; see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        d.subsec_millis()
+    )
+}
+
+// SetTrace and LogTrace aren't in lsp_types.
+pub enum SetTrace {}
+
+impl Notification for SetTrace {
+    type Params = SetTraceParams;
+    const METHOD: &'static str = "$/setTrace";
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct SetTraceParams {
+    pub value: TraceOption,
+}
+
+pub enum LogTrace {}
+
+impl Notification for LogTrace {
+    type Params = LogTraceParams;
+    const METHOD: &'static str = "$/logTrace";
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct LogTraceParams {
+    pub message: String,
+    pub verbose: Option<String>,
+}
+
+// Incoming is what a client's reader receives from its server.
+#[derive(Debug)]
+pub enum Incoming {
+    // Message is the body of a JSON-RPC message.
+    Message(Vec<u8>),
+    // Error is a frame that couldn't be read. Reading continues with the
+    // next frame.
+    Error(String),
+    // Exit means the server closed its output.
+    Exit,
+}
+
+// read_message reads a message framed by a base protocol header.
+fn read_message<R: BufRead>(r: &mut R) -> Incoming {
+    let mut content_len = None;
+    let mut err = None;
+    let mut headers = 0;
+    loop {
+        let mut buf = vec![];
+        match r.read_until(b'\n', &mut buf) {
+            Ok(0) | Err(_) => return Incoming::Exit,
+            Ok(_) => {}
+        }
+        let line = String::from_utf8_lossy(&buf);
+        let line = line.trim_end_matches(&['\r', '\n'][..]);
+        if line.is_empty() {
+            // Skip stray blank lines between messages.
+            if headers == 0 {
+                continue;
+            }
+            break;
+        }
+        headers += 1;
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name.trim().to_ascii_lowercase(), value.trim()),
+            None => {
+                err = Some(format!("malformed header {:?}", line));
+                continue;
+            }
+        };
+        match name.as_str() {
+            "content-length" => match value.parse() {
+                Ok(n) => content_len = Some(n),
+                Err(_) => err = Some(format!("bad Content-Length {:?}", value)),
+            },
+            "content-type" => {
+                let charset = value
+                    .split(';')
+                    .filter_map(|p| p.trim().strip_prefix("charset="))
+                    .next();
+                if let Some(c) = charset {
+                    if !c.eq_ignore_ascii_case("utf-8") && !c.eq_ignore_ascii_case("utf8") {
+                        err = Some(format!("unsupported charset {:?}", c));
+                    }
+                }
+            }
+            // Other headers are allowed and ignored.
+            _ => {}
+        }
     }
+    let len = match content_len {
+        Some(n) => n,
+        None => return Incoming::Error(err.unwrap_or_else(|| "missing Content-Length".into())),
+    };
+    let mut body = vec![0; len];
+    if r.read_exact(&mut body).is_err() {
+        return Incoming::Exit;
+    }
+    if let Some(err) = err {
+        return Incoming::Error(err);
+    }
+    if std::str::from_utf8(&body).is_err() {
+        return Incoming::Error("message is not UTF-8".into());
+    }
+    Incoming::Message(body)
 }
 
 #[derive(serde::Serialize)]
@@ -171,6 +484,20 @@ struct RequestMessage<P> {
     params: P,
 }
 
+#[derive(serde::Serialize)]
+struct ResponseMessage<R> {
+    jsonrpc: &'static str,
+    id: usize,
+    result: R,
+}
+
+#[derive(serde::Serialize)]
+struct ErrorMessage {
+    jsonrpc: &'static str,
+    id: usize,
+    error: ResponseError,
+}
+
 #[derive(serde::Serialize)]
 struct NotificationMessage<P> {
     jsonrpc: &'static str,
@@ -187,29 +514,226 @@ pub struct DeMessage {
     pub error: Option<ResponseError>,
 }
 
-#[derive(Debug, serde::Deserialize)]
+// METHOD_NOT_FOUND is the error code for requests the receiver doesn't
+// support.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[allow(dead_code)]
 pub struct ResponseError {
     pub code: i64,
     pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub data: Option<serde_json::Value>,
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use crate::fakelsp::{spawn, Step};
     use crate::lsp::*;
 
     #[test]
     fn lsp() {
-        let (mut l, _) = Client::new(
-            "rls".to_string(),
-            ".rs".to_string(),
-            "rls",
-            std::iter::empty(),
-            Some("file:///home/mjibson/go/src/github.com/mjibson/plan9".to_string()),
+        let (mut c, id, fake) = spawn(
+            "fake",
+            vec![
+                Step::Respond("initialize", json!({"capabilities": {}})),
+                Step::Expect("initialized"),
+                Step::Raw("Content-Length: 2\r\n\r\n{}"),
+            ],
+        );
+        let msg = match c.msg_r.recv_timeout(Duration::from_secs(5)).unwrap() {
+            Incoming::Message(msg) => msg,
+            msg => panic!("unexpected {:?}", msg),
+        };
+        let msg: DeMessage = serde_json::from_slice(&msg).unwrap();
+        assert_eq!(msg.id, Some(id));
+        assert!(msg.result.is_some());
+        c.notify::<Initialized>(InitializedParams {}).unwrap();
+        let msg = c.msg_r.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(msg, Incoming::Message(m) if m == b"{}"));
+        fake.wait();
+        let params = &fake.received("initialize")[0];
+        assert_eq!(params["capabilities"]["window"]["workDoneProgress"], false);
+    }
+
+    // answer accepts a connection and answers the Initialize request on it.
+    fn answer<S: Read + Write>(mut conn: S) {
+        let mut r = BufReader::new(&mut conn);
+        let mut line = String::new();
+        let mut len = 0;
+        while r.read_line(&mut line).unwrap() > 0 && line.trim() != "" {
+            if let Some(n) = line.trim().strip_prefix("Content-Length: ") {
+                len = n.parse().unwrap();
+            }
+            line.clear();
+        }
+        let mut buf = vec![0; len];
+        r.read_exact(&mut buf).unwrap();
+        let msg: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(msg["method"], "initialize");
+        let resp = json!({"jsonrpc": "2.0", "id": msg["id"], "result": {"capabilities": {}}});
+        let resp = resp.to_string();
+        write!(conn, "Content-Length: {}\r\n\r\n{}", resp.len(), resp).unwrap();
+    }
+
+    #[test]
+    fn shutdown() {
+        let (c, _, fake) = spawn(
+            "fake",
+            vec![Step::Respond("shutdown", json!(null)), Step::Expect("exit")],
+        );
+        c.shutdown(Duration::from_secs(5)).unwrap();
+        fake.wait();
+
+        // A server that doesn't answer is killed.
+        let (c, _) = Client::new(
+            "sleep".to_string(),
+            Transport::Stdio {
+                program: "sleep".to_string(),
+                args: vec!["10".to_string()],
+                env: vec![],
+                cwd: None,
+            },
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        let err = c.shutdown(Duration::from_millis(100)).unwrap_err();
+        assert_eq!(err.to_string(), "sleep did not shut down in time");
+    }
+
+    #[test]
+    fn transport() {
+        let connect = |transport| {
+            let (c, id) = Client::new("dial".to_string(), transport, None, None, None, None)?;
+            let msg = match c.msg_r.recv_timeout(Duration::from_secs(5))? {
+                Incoming::Message(msg) => msg,
+                msg => bail!("unexpected {:?}", msg),
+            };
+            let msg: DeMessage = serde_json::from_slice(&msg)?;
+            assert_eq!(msg.id, Some(id));
+            Ok::<(), anyhow::Error>(())
+        };
+
+        let l = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = l.local_addr().unwrap().port();
+        thread::spawn(move || answer(l.accept().unwrap().0));
+        connect(Transport::Dial(format!("tcp!127.0.0.1!{}", port))).unwrap();
+
+        let path = std::env::temp_dir().join(format!("acre-lsp.{}", std::process::id()));
+        let l = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        thread::spawn(move || answer(l.accept().unwrap().0));
+        connect(Transport::Dial(format!("unix!{}", path.display()))).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(connect(Transport::Dial("localhost:4389".to_string())).is_err());
+        assert!(connect(Transport::Stdio {
+            program: "/nonexistent/server".to_string(),
+            args: vec![],
+            env: vec![],
+            cwd: None,
+        })
+        .is_err());
+
+        // Servers run with env added and in cwd.
+        let script = r#"msg="[\"$ACRE_TEST\", \"$(pwd)\"]"; printf "Content-Length: ${#msg}\r\n\r\n$msg"; cat"#;
+        let (c, _) = Client::new(
+            "sh".to_string(),
+            Transport::Stdio {
+                program: "sh".to_string(),
+                args: vec!["-c".to_string(), script.to_string()],
+                env: vec![("ACRE_TEST".to_string(), "yes".to_string())],
+                cwd: Some("/".to_string()),
+            },
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        match c.msg_r.recv_timeout(Duration::from_secs(5)).unwrap() {
+            Incoming::Message(msg) => assert_eq!(msg, br#"["yes", "/"]"#),
+            msg => panic!("unexpected {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn read_message() {
+        let data = concat!(
+            "Content-Length: 2\r\n\r\n{}",
+            "\r\ncontent-length: 4\r\nContent-Type: application/vscode-jsonrpc; charset=utf-8\r\n",
+            "X-Unknown: yes\r\n\r\n[1 ]",
+            "Content-Length: 2\r\nContent-Type: text/plain; charset=latin1\r\n\r\n{}",
+            "Content-Length: two\r\n\r\n",
+            "garbage\r\n\r\n",
+        );
+        let mut data = data.as_bytes().to_vec();
+        data.extend_from_slice(b"Content-Length: 2\r\n\r\n\xff}Content-Length: 10\r\n\r\n{}");
+        let mut r = std::io::Cursor::new(data);
+        let mut next = || format!("{:?}", super::read_message(&mut r));
+        assert_eq!(next(), format!("{:?}", Incoming::Message(b"{}".to_vec())));
+        assert_eq!(next(), format!("{:?}", Incoming::Message(b"[1 ]".to_vec())));
+        assert_eq!(next(), r#"Error("unsupported charset \"latin1\"")"#);
+        assert_eq!(next(), r#"Error("bad Content-Length \"two\"")"#);
+        assert_eq!(next(), r#"Error("malformed header \"garbage\"")"#);
+        assert_eq!(next(), r#"Error("message is not UTF-8")"#);
+        // The server exited partway through a message.
+        assert_eq!(next(), "Exit");
+        assert_eq!(next(), "Exit");
+    }
+
+    #[test]
+    fn log() {
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            timestamp(UNIX_EPOCH + Duration::from_millis(1_700_000_000_123)),
+            "2023-11-14T22:13:20.123Z"
+        );
+        assert_eq!(
+            summary(br#"{"id":1,"method":"initialize"}"#),
+            "request 1 initialize"
+        );
+        assert_eq!(summary(br#"{"method":"exit"}"#), "notification exit");
+        assert_eq!(summary(br#"{"id":1,"result":null}"#), "response 1");
+
+        // Messages are summarized and stderr is captured.
+        let path = std::env::temp_dir().join(format!("acre-log.{}", std::process::id()));
+        let log = Log::open(&path, TraceOption::Messages).unwrap();
+        let (c, _) = Client::new(
+            "sh".to_string(),
+            Transport::Stdio {
+                program: "sh".to_string(),
+                args: vec![
+                    "-c".to_string(),
+                    "echo oops >&2; cat >/dev/null".to_string(),
+                ],
+                env: vec![],
+                cwd: None,
+            },
+            Some(log),
+            None,
             None,
             None,
         )
         .unwrap();
-        l.wait().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let text = loop {
+            let text = std::fs::read_to_string(&path).unwrap();
+            if text.contains(" stderr oops\n") || Instant::now() > deadline {
+                break text;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        drop(c);
+        std::fs::remove_file(&path).unwrap();
+        assert!(text.contains(" --> request 1 initialize\n"), "{}", text);
+        assert!(text.contains(" stderr oops\n"), "{}", text);
     }
 }
@@ -1,13 +1,16 @@
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Write;
 use std::fs::metadata;
 use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
-use anyhow::{bail, Error, Result};
-use crossbeam_channel::{bounded, Receiver, Select};
-use diff;
+use anyhow::{anyhow, bail, Error, Result};
+use crossbeam_channel::{after, bounded, never, Receiver, Select};
 use lazy_static::lazy_static;
 use lsp_types::{notification::*, request::*, *};
 use nine::p2000::OpenMode;
@@ -15,48 +18,372 @@ use regex::Regex;
 use serde::Deserialize;
 use serde_json::Value;
 
-use plan9::{acme::*, plumb};
+use plan9::{acme::*, font, fsys::Fsys, plumb};
 
+mod check;
+mod editorconfig;
+#[cfg(test)]
+mod fakelsp;
 mod lsp;
 
-#[derive(Deserialize)]
+// log prints a message to stderr if --log-level allows its level.
+macro_rules! log {
+    ($level:ident, $($arg:tt)*) => {
+        if LogLevel::$level as u8 <= LOG_LEVEL.load(AtomicOrdering::Relaxed) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+// LogLevel is how much acre prints to stderr.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<LogLevel> {
+        Ok(match s {
+            "error" => LogLevel::Error,
+            "warn" => LogLevel::Warn,
+            "info" => LogLevel::Info,
+            "debug" => LogLevel::Debug,
+            _ => bail!("unknown log level {:?}", s),
+        })
+    }
+}
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Warn as u8);
+
+#[derive(Clone, Deserialize)]
 struct TomlConfig {
     servers: HashMap<String, ConfigServer>,
 }
 
+// How long a server has to answer shutdown, and then to exit.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+// How long a server has to answer other requests, unless configured.
+const REQUEST_TIMEOUT: u64 = 30;
+
+// Starter starts the client for a configured server, logging to log,
+// returning it with the id of its Initialize request.
+type Starter =
+    Box<dyn FnMut(&str, &ConfigServer, Option<lsp::Log>) -> Result<(lsp::Client, usize)>>;
+
+// Matchers maps files, by regex, to names.
+type Matchers = Vec<(Regex, String)>;
+
 #[derive(Clone, Deserialize)]
 struct ConfigServer {
     executable: Option<String>,
+    args: Option<Vec<String>>,
+    // Variables added to the server's environment.
+    env: Option<HashMap<String, String>>,
+    // Directory to run the server in.
+    cwd: Option<String>,
+    // Address of an already running server, like tcp!localhost!4389.
+    connect: Option<String>,
     files: String,
     root_uri: Option<String>,
     workspace_folders: Option<Vec<String>>,
     options: Option<Value>,
+    // section -> settings, for workspace/configuration.
+    settings: Option<Value>,
+    actions_on_put: Option<Vec<CodeActionKind>>,
+    format_on_put: Option<bool>,
+    // Files or directories marking the root of a workspace, like Cargo.toml.
+    root_markers: Option<Vec<String>>,
+    // Prefer this server for requests with a single answer, like definition
+    // or hover, when several servers handle a file.
+    primary: Option<bool>,
+    // Seconds to keep the server running after its last window closes.
+    idle_timeout: Option<u64>,
+    // How much of the conversation with the server to write to its log.
+    trace: Option<TraceOption>,
+    // Seconds the server has to answer a request before it is cancelled.
+    request_timeout: Option<u64>,
+    // files regex -> language id, overriding language_id.
+    language_ids: Option<BTreeMap<String, String>>,
+    // How to format files on Put.
+    formatting: Option<FormattingConfig>,
+}
+
+// FormattingConfig is how a server should format files. .editorconfig files
+// override it, and unset settings come from acme's tab stop and the defaults.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+struct FormattingConfig {
+    tab_size: Option<u32>,
+    insert_spaces: Option<bool>,
+    trim_trailing_whitespace: Option<bool>,
+    insert_final_newline: Option<bool>,
+    trim_final_newlines: Option<bool>,
+    // files regex -> settings overriding these for matching files.
+    files: Option<BTreeMap<String, FormattingConfig>>,
+}
+
+impl FormattingConfig {
+    // merge replaces these settings with the ones over sets.
+    fn merge(&mut self, over: &FormattingConfig) {
+        self.tab_size = over.tab_size.or(self.tab_size);
+        self.insert_spaces = over.insert_spaces.or(self.insert_spaces);
+        self.trim_trailing_whitespace = over
+            .trim_trailing_whitespace
+            .or(self.trim_trailing_whitespace);
+        self.insert_final_newline = over.insert_final_newline.or(self.insert_final_newline);
+        self.trim_final_newlines = over.trim_final_newlines.or(self.trim_final_newlines);
+    }
+    // options returns the formatting options for filename: these settings,
+    // then those for files matching filename, then its .editorconfig. The
+    // tab size is taken from tab_size if none of those set it.
+    fn options(&self, filename: &str, tab_size: impl FnOnce() -> u32) -> Result<FormattingOptions> {
+        let mut f = self.clone();
+        for (files, over) in self.files.iter().flatten() {
+            if Regex::new(files)?.is_match(filename) {
+                f.merge(over);
+            }
+        }
+        f.merge(&editorconfig::formatting(Path::new(filename))?);
+        Ok(FormattingOptions {
+            tab_size: f.tab_size.unwrap_or_else(tab_size),
+            insert_spaces: f.insert_spaces.unwrap_or(false),
+            properties: HashMap::new(),
+            trim_trailing_whitespace: Some(f.trim_trailing_whitespace.unwrap_or(true)),
+            insert_final_newline: Some(f.insert_final_newline.unwrap_or(true)),
+            trim_final_newlines: Some(f.trim_final_newlines.unwrap_or(true)),
+        })
+    }
+}
+
+// acme_tabstop returns the tab stop of w in characters. acme's ctl file
+// reports it in pixels, as the tab stop times the width of 0 in the
+// window's font. If the font can't be read, this reads $tabstop, as acme
+// does, defaulting to 4 like acme.
+fn acme_tabstop(w: &mut Win) -> u32 {
+    let ctl = w.info().ok().and_then(|info| {
+        let zero = font::char_width(info.font.as_deref()?, '0')
+            .ok()
+            .filter(|w| *w > 0)?;
+        Some(info.tabwidth? as u32 / zero)
+    });
+    ctl.or_else(|| std::env::var("tabstop").ok()?.parse().ok())
+        .filter(|t| *t > 0)
+        .unwrap_or(4)
+}
+
+impl ConfigServer {
+    // needs_restart reports whether changing from this config to other
+    // needs the server restarted, because settings it is only given when
+    // it starts changed.
+    fn needs_restart(&self, other: &ConfigServer) -> bool {
+        (
+            &self.executable,
+            &self.args,
+            &self.env,
+            &self.cwd,
+            &self.connect,
+            &self.root_uri,
+            &self.workspace_folders,
+            &self.options,
+            &self.trace,
+        ) != (
+            &other.executable,
+            &other.args,
+            &other.env,
+            &other.cwd,
+            &other.connect,
+            &other.root_uri,
+            &other.workspace_folders,
+            &other.options,
+            &other.trace,
+        )
+    }
+    // with_project returns the config with project's settings merged over
+    // it. Options and settings are merged key by key; the rest are
+    // replaced.
+    fn with_project(&self, project: &ProjectServer) -> ConfigServer {
+        let mut c = self.clone();
+        for (base, over) in [
+            (&mut c.options, &project.options),
+            (&mut c.settings, &project.settings),
+        ] {
+            if let Some(over) = over {
+                match base {
+                    Some(base) => merge_value(base, over.clone()),
+                    None => *base = Some(over.clone()),
+                }
+            }
+        }
+        if project.root_uri.is_some() {
+            c.root_uri = project.root_uri.clone();
+        }
+        if project.workspace_folders.is_some() {
+            c.workspace_folders = project.workspace_folders.clone();
+        }
+        if project.actions_on_put.is_some() {
+            c.actions_on_put = project.actions_on_put.clone();
+        }
+        if project.format_on_put.is_some() {
+            c.format_on_put = project.format_on_put;
+        }
+        c
+    }
+}
+
+// The name of per-project configuration files.
+const PROJECT_TOML: &str = ".acre.toml";
+
+// ProjectConfig is a .acre.toml file, whose settings are merged over the
+// global ones for the files under its directory.
+#[derive(Clone, Deserialize)]
+struct ProjectConfig {
+    servers: HashMap<String, ProjectServer>,
+}
+
+#[derive(Clone, Deserialize)]
+struct ProjectServer {
+    root_uri: Option<String>,
+    workspace_folders: Option<Vec<String>>,
+    options: Option<Value>,
+    settings: Option<Value>,
     actions_on_put: Option<Vec<CodeActionKind>>,
     format_on_put: Option<bool>,
 }
 
+// project_folders returns the workspace folders of an instance serving the
+// project in dir. The project is the root unless it says otherwise.
+fn project_folders(project: &ProjectServer, dir: &Path) -> Result<Vec<String>> {
+    Ok(match (&project.workspace_folders, &project.root_uri) {
+        (Some(folders), _) => folders.clone(),
+        (None, Some(root)) => vec![root.clone()],
+        (None, None) => vec![dir_uri(dir)?],
+    })
+}
+
+// merge_value merges the objects in over into base, replacing anything
+// else.
+fn merge_value(base: &mut Value, over: Value) {
+    match (base, over) {
+        (Value::Object(base), Value::Object(over)) => {
+            for (k, v) in over {
+                merge_value(base.entry(k).or_insert(Value::Null), v);
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+const USAGE: &str = "usage: acre [check] [--config file] [--namespace dir] [--window-name name]
+            [--log-level error|warn|info|debug] [--server name]...";
+
+// Flags are acre's command line arguments.
+#[derive(Debug, Default, PartialEq)]
+struct Flags {
+    // Check the config and environment instead of running.
+    check: bool,
+    config: Option<PathBuf>,
+    namespace: Option<String>,
+    window_name: Option<String>,
+    log_level: Option<LogLevel>,
+    // Run only these servers.
+    servers: Vec<String>,
+}
+
+// parse_flags parses args, which don't include the program name. Flags
+// take their value as the next argument or after an =.
+fn parse_flags<I: Iterator<Item = String>>(mut args: I) -> Result<Flags> {
+    let mut flags = Flags::default();
+    while let Some(arg) = args.next() {
+        if arg == "check" {
+            flags.check = true;
+            continue;
+        }
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || match inline.clone().or_else(|| args.next()) {
+            Some(value) => Ok(value),
+            None => Err(anyhow!("{} needs a value", flag)),
+        };
+        match flag {
+            "--config" => flags.config = Some(PathBuf::from(value()?)),
+            "--namespace" => flags.namespace = Some(value()?),
+            "--window-name" => flags.window_name = Some(value()?),
+            "--log-level" => flags.log_level = Some(value()?.parse()?),
+            "--server" => flags.servers.push(value()?),
+            _ => bail!("unknown argument {:?}\n{}", arg, USAGE),
+        }
+    }
+    Ok(flags)
+}
+
 fn main() -> Result<()> {
-    let dir = xdg::BaseDirectories::new()?;
-    const ACRE_TOML: &str = "acre.toml";
-    let config = match dir.find_config_file(ACRE_TOML) {
-        Some(c) => c,
+    let flags = match parse_flags(std::env::args().skip(1)) {
+        Ok(flags) => flags,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+    if let Some(level) = flags.log_level {
+        LOG_LEVEL.store(level as u8, AtomicOrdering::Relaxed);
+    }
+    // The plan9 crate finds acme and the plumber in $NAMESPACE.
+    if let Some(ns) = &flags.namespace {
+        std::env::set_var("NAMESPACE", ns);
+    }
+    let path = match &flags.config {
+        Some(path) => path.clone(),
         None => {
-            let mut path = dir.get_config_home();
-            path.push(ACRE_TOML);
-            eprintln!("could not find {}", path.to_str().unwrap());
-            std::process::exit(1);
+            let dir = xdg::BaseDirectories::new()?;
+            const ACRE_TOML: &str = "acre.toml";
+            match dir.find_config_file(ACRE_TOML) {
+                Some(c) => c,
+                None => {
+                    let mut path = dir.get_config_home();
+                    path.push(ACRE_TOML);
+                    eprintln!("could not find {}", path.to_str().unwrap());
+                    std::process::exit(1);
+                }
+            }
         }
     };
-    let config = std::fs::read_to_string(config)?;
-    let config: TomlConfig = toml::from_str(&config)?;
+    if flags.check {
+        std::process::exit(check::check(&path) as i32);
+    }
+    let config = only_servers(read_config(&path)?, &flags.servers)?;
     if config.servers.is_empty() {
         println!("empty servers in configuration file");
         std::process::exit(1);
     }
-    let mut s = Server::new(config)?;
+    let mut s = Server::new(config, path, &flags, FSYS.clone())?;
     s.wait()
 }
 
+// only_servers removes the servers not in names from config, unless names
+// is empty.
+fn only_servers(mut config: TomlConfig, names: &[String]) -> Result<TomlConfig> {
+    if names.is_empty() {
+        return Ok(config);
+    }
+    for name in names {
+        if !config.servers.contains_key(name) {
+            bail!("unknown server {:?}", name);
+        }
+    }
+    config.servers.retain(|name, _| names.contains(name));
+    Ok(config)
+}
+
+fn read_config(path: &Path) -> Result<TomlConfig> {
+    let config = std::fs::read_to_string(path)?;
+    Ok(toml::from_str(&config)?)
+}
+
 struct WDProgress {
     name: String,
     percentage: Option<u32>,
@@ -97,6 +424,19 @@ impl std::fmt::Display for WDProgress {
     }
 }
 
+// Instance is a copy of a configured server. Servers with root_markers get
+// one per workspace root unless they can take more workspace folders.
+struct Instance {
+    // The configured server name.
+    server: String,
+    // Workspace folder URIs found from root markers or a project.
+    folders: Vec<String>,
+    // The directory of the project and the server's config merged with its
+    // settings, for instances serving a project.
+    project: Option<PathBuf>,
+    config: Option<ConfigServer>,
+}
+
 #[derive(Debug, Clone)]
 enum Action {
     Command(CodeActionOrCommand),
@@ -120,24 +460,32 @@ impl ClientId {
 
 struct Server {
     config: TomlConfig,
+    // The acme file system.
+    acme: Arc<Mutex<Fsys>>,
     w: Win,
     ws: HashMap<usize, ServerWin>,
     // Sorted Vec of (filenames, win id) to know which order to print windows in.
     names: Vec<(String, usize)>,
-    // Vec of (position, win id) to map Look locations to windows.
+    // zerox clone win id -> win id of the ServerWin they share.
+    clones: HashMap<usize, usize>,
+    // Vec of (rune position, win id) to map Look locations to windows.
     addr: Vec<(usize, usize)>,
 
     body: String,
     output: String,
     focus: String,
-    progress: HashMap<String, WDProgress>,
-    // file name -> list of diagnostics
-    diags: HashMap<String, Vec<String>>,
-    // request (client_name, id) -> (method, file Url)
-    requests: HashMap<ClientId, (String, Url)>,
+    // (client name, token) -> work done progress
+    progress: HashMap<(String, ProgressToken), WDProgress>,
+    // (client name, file name) -> list of diagnostics
+    diags: HashMap<(String, String), Vec<String>>,
+    // request (client_name, id) -> (method, file Url, when it was sent)
+    requests: HashMap<ClientId, (String, Url, Instant)>,
+    // Vec of position and ClientId to map Look locations to requests.
+    request_addrs: Vec<(usize, ClientId)>,
     actions: HashMap<ClientId, Vec<Action>>,
-    // Vec of position and (ClientId, index) into the vec of actions.
-    action_addrs: Vec<(usize, (ClientId, usize))>,
+    // Vec of position and (ClientId, index) into the vec of actions. None
+    // marks the end of the actions.
+    action_addrs: Vec<(usize, Option<(ClientId, usize)>)>,
 
     // current window info
     current_hover: Option<String>,
@@ -146,34 +494,69 @@ struct Server {
     ev_r: Receiver<Event>,
     err_r: Receiver<Error>,
 
+    start: Starter,
+    // The config file, to reload it from.
+    config_path: Option<PathBuf>,
+    // The servers to run, if not all of them.
+    only_servers: Vec<String>,
+    // Directory of the servers' logs.
+    log_dir: PathBuf,
+    // (files regex, server name) for each configured server, primary servers
+    // first and then by name.
+    matchers: Matchers,
+    // server name -> (files regex, language id) overrides
+    language_ids: HashMap<String, Matchers>,
+    // directory -> the project it is in, or why its .acre.toml couldn't be
+    // read, until the next Reload
+    projects: HashMap<PathBuf, std::result::Result<Project, String>>,
+    // client name -> instance
+    instances: HashMap<String, Instance>,
     // client name -> client
     clients: HashMap<String, lsp::Client>,
+    // client name -> when to restart it, or None if it was stopped
+    down: HashMap<String, Option<Instant>>,
+    // client name -> restarts since it last initialized
+    attempts: HashMap<String, u32>,
+    // client name -> when it was left without windows
+    idle: HashMap<String, Instant>,
+    // Vec of (rune position, client name) to map Look locations to servers.
+    server_addrs: Vec<(usize, String)>,
     // client name -> capabilities
     capabilities: HashMap<String, lsp_types::ServerCapabilities>,
-    // file name -> client name
-    files: HashMap<String, String>,
+    // file name -> client names, primary first
+    files: HashMap<String, Vec<String>>,
     // list of LSP message IDs to auto-run actions
     autorun: HashMap<usize, ()>,
 }
 
 struct ServerWin {
+    name: String,
     w: Win,
     url: Url,
     version: i32,
-    client: String,
+    // Clients the file is open in, primary first.
+    clients: Vec<String>,
 }
 
 impl ServerWin {
-    fn new(name: String, w: Win, client: String) -> Result<ServerWin> {
+    fn new(name: String, w: Win) -> Result<ServerWin> {
         let url = Url::parse(&format!("file://{}", name))?;
         let version = 1;
         Ok(ServerWin {
+            name,
             w,
             url,
             version,
-            client,
+            clients: vec![],
         })
     }
+    // rename points the window at a new file, which must be opened again.
+    fn rename(&mut self, name: String) -> Result<()> {
+        self.url = Url::parse(&format!("file://{}", name))?;
+        self.name = name;
+        self.version = 1;
+        Ok(())
+    }
     fn pos(&mut self) -> Result<(u32, u32)> {
         self.w.ctl("addr=dot")?;
         // TODO: convert these character (rune) offsets to byte offsets.
@@ -214,48 +597,185 @@ impl ServerWin {
     }
 }
 
+// start_client starts the client for a configured server.
+fn start_client(
+    name: &str,
+    server: &ConfigServer,
+    log: Option<lsp::Log>,
+) -> Result<(lsp::Client, usize)> {
+    let transport = match &server.connect {
+        Some(addr) => lsp::Transport::Dial(addr.clone()),
+        None => lsp::Transport::Stdio {
+            program: expand(server.executable.as_deref().unwrap_or(name)),
+            args: server.args.iter().flatten().map(|a| expand(a)).collect(),
+            env: server
+                .env
+                .iter()
+                .flatten()
+                .map(|(k, v)| (k.clone(), expand(v)))
+                .collect(),
+            cwd: server.cwd.as_deref().map(expand),
+        },
+    };
+    connect_client(name, server, transport, log)
+}
+
+// language_id returns the LSP language identifier of filename, or
+// "plaintext" if it isn't known.
+fn language_id(filename: &str) -> &'static str {
+    let base = Path::new(filename)
+        .file_name()
+        .and_then(|b| b.to_str())
+        .unwrap_or("");
+    match base {
+        "Makefile" | "makefile" | "GNUmakefile" => return "makefile",
+        "Dockerfile" => return "dockerfile",
+        _ => {}
+    }
+    let ext = match base.rsplit_once('.') {
+        Some((_, ext)) => ext.to_ascii_lowercase(),
+        None => return "plaintext",
+    };
+    match ext.as_str() {
+        "bat" | "cmd" => "bat",
+        "bib" => "bibtex",
+        "c" => "c",
+        "clj" | "cljs" | "cljc" | "edn" => "clojure",
+        "coffee" => "coffeescript",
+        "cc" | "cpp" | "cxx" | "c++" | "h" | "hh" | "hpp" | "hxx" => "cpp",
+        "cs" => "csharp",
+        "css" => "css",
+        "d" => "d",
+        "dart" => "dart",
+        "diff" | "patch" => "diff",
+        "erl" | "hrl" => "erlang",
+        "ex" | "exs" => "elixir",
+        "fs" | "fsi" | "fsx" => "fsharp",
+        "go" => "go",
+        "groovy" | "gradle" => "groovy",
+        "hs" | "lhs" => "haskell",
+        "htm" | "html" | "xhtml" => "html",
+        "ini" => "ini",
+        "java" => "java",
+        "js" | "mjs" | "cjs" => "javascript",
+        "jsx" => "javascriptreact",
+        "json" => "json",
+        "jsonc" => "jsonc",
+        "kt" | "kts" => "kotlin",
+        "less" => "less",
+        "lua" => "lua",
+        "m" => "objective-c",
+        "mm" => "objective-cpp",
+        "md" | "markdown" => "markdown",
+        "ml" | "mli" => "ocaml",
+        "php" => "php",
+        "pl" | "pm" => "perl",
+        "ps1" | "psm1" => "powershell",
+        "py" | "pyi" => "python",
+        "r" => "r",
+        "rb" => "ruby",
+        "rs" => "rust",
+        "sass" => "sass",
+        "scala" | "sc" => "scala",
+        "scss" => "scss",
+        "sh" | "bash" | "zsh" => "shellscript",
+        "sql" => "sql",
+        "swift" => "swift",
+        "tex" => "latex",
+        "toml" => "toml",
+        "ts" | "mts" | "cts" => "typescript",
+        "tsx" => "typescriptreact",
+        "vb" => "vb",
+        "vue" => "vue",
+        "xml" | "xsd" | "xsl" => "xml",
+        "yaml" | "yml" => "yaml",
+        "zig" => "zig",
+        _ => "plaintext",
+    }
+}
+
+// state_dir returns the XDG state directory.
+fn state_dir() -> PathBuf {
+    match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(expand("~/.local/state")),
+    }
+}
+
+// expand replaces a leading ~ with $HOME, and $VAR or ${VAR} with the value
+// of the environment variable VAR. Unset variables expand to nothing.
+fn expand(s: &str) -> String {
+    lazy_static! {
+        static ref VAR: Regex = Regex::new(r"\$(\w+|\{(\w+)\})").unwrap();
+    }
+    let s = match s.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("$HOME{}", rest),
+        _ => s.to_string(),
+    };
+    VAR.replace_all(&s, |c: &regex::Captures| {
+        let name = c.get(2).or_else(|| c.get(1)).unwrap().as_str();
+        std::env::var(name).unwrap_or_default()
+    })
+    .into_owned()
+}
+
+// connect_client returns a client for a configured server reached over
+// transport.
+fn connect_client(
+    name: &str,
+    server: &ConfigServer,
+    transport: lsp::Transport,
+    log: Option<lsp::Log>,
+) -> Result<(lsp::Client, usize)> {
+    lsp::Client::new(
+        name.to_string(),
+        transport,
+        log,
+        server.root_uri.clone(),
+        server.workspace_folders.clone(),
+        server.options.clone(),
+    )
+}
+
 impl Server {
-    fn new(config: TomlConfig) -> Result<Server> {
-        let mut clients = vec![];
-        let mut requests = HashMap::new();
-        for (name, server) in config.servers.clone() {
-            let (client, msg_id) = lsp::Client::new(
-                name.clone(),
-                server.files,
-                server.executable.unwrap_or(name.clone()),
-                std::iter::empty(),
-                server.root_uri,
-                server.workspace_folders,
-                server.options,
-            )?;
-            requests.insert(
-                ClientId::new(name, msg_id),
-                (Initialize::METHOD.into(), Url::parse("file:///").unwrap()),
-            );
-            clients.push(client);
+    fn new(
+        config: TomlConfig,
+        path: PathBuf,
+        flags: &Flags,
+        acme: Arc<Mutex<Fsys>>,
+    ) -> Result<Server> {
+        let mut s = Server::with_starter(config, acme, Box::new(start_client))?;
+        if let Some(name) = &flags.window_name {
+            s.w.name(name)?;
         }
+        s.config_path = Some(path);
+        s.only_servers = flags.servers.clone();
+        Ok(s)
+    }
+    // with_starter returns a Server that starts its clients with start.
+    fn with_starter(config: TomlConfig, acme: Arc<Mutex<Fsys>>, start: Starter) -> Result<Server> {
+        let (matchers, language_ids) = compile(&config)?;
 
         let (log_s, log_r) = bounded(0);
         let (ev_s, ev_r) = bounded(0);
         let (err_s, err_r) = bounded(0);
-        let mut w = Win::new()?;
+        let mut w = Win::new_in(&acme)?;
         w.name("acre")?;
         let mut wev = w.events()?;
-        let mut cls = HashMap::new();
-        for c in clients {
-            let name = c.name.clone();
-            cls.insert(name, c);
-        }
+        let log_acme = Arc::clone(&acme);
         let s = Server {
+            acme,
             w,
             ws: HashMap::new(),
             names: vec![],
+            clones: HashMap::new(),
             addr: vec![],
             output: "".to_string(),
             body: "".to_string(),
             focus: "".to_string(),
             progress: HashMap::new(),
-            requests,
+            requests: HashMap::new(),
+            request_addrs: vec![],
             actions: HashMap::new(),
             action_addrs: vec![],
             diags: HashMap::new(),
@@ -263,7 +783,19 @@ impl Server {
             log_r,
             ev_r,
             err_r,
-            clients: cls,
+            start,
+            config_path: None,
+            only_servers: vec![],
+            log_dir: state_dir().join("acre"),
+            matchers,
+            language_ids,
+            projects: HashMap::new(),
+            instances: HashMap::new(),
+            clients: HashMap::new(),
+            down: HashMap::new(),
+            attempts: HashMap::new(),
+            idle: HashMap::new(),
+            server_addrs: vec![],
             capabilities: HashMap::new(),
             files: HashMap::new(),
             config,
@@ -273,28 +805,19 @@ impl Server {
         thread::Builder::new()
             .name("LogReader".to_string())
             .spawn(move || {
-                let mut log = LogReader::new().unwrap();
+                let mut log = LogReader::new_in(&log_acme).unwrap();
                 loop {
                     match log.read() {
-                        Ok(ev) => match ev.op.as_str() {
-                            "new" | "del" | "focus" | "put" => {
-                                if cfg!(debug_assertions) {
-                                    println!("log reader: {:?}", ev);
-                                }
-                                match log_s.send(ev) {
-                                    Ok(_) => {}
-                                    Err(err) => {
-                                        println!("log_s send err {}", err);
-                                        return;
-                                    }
-                                }
-                            }
-                            _ => {
-                                if cfg!(debug_assertions) {
-                                    println!("log reader: {:?} [uncaught]", ev);
+                        Ok(ev) => {
+                            log!(Debug, "log reader: {:?}", ev);
+                            match log_s.send(ev) {
+                                Ok(_) => {}
+                                Err(err) => {
+                                    log!(Error, "log_s send err {}", err);
+                                    return;
                                 }
                             }
-                        },
+                        }
                         Err(err) => {
                             err_s1.send(err).unwrap();
                             return;
@@ -306,10 +829,10 @@ impl Server {
         thread::Builder::new()
             .name("WindowEvents".to_string())
             .spawn(move || loop {
-                let mut ev = match wev.read_event() {
+                let ev = match wev.read_event() {
                     Ok(ev) => ev,
                     Err(err) => {
-                        println!("read event err {}", err);
+                        log!(Error, "read event err {}", err);
                         return;
                     }
                 };
@@ -318,7 +841,7 @@ impl Server {
                         "Del" => {
                             return;
                         }
-                        "Get" => {
+                        "Get" | "Reload" => {
                             ev_s.send(ev).unwrap();
                         }
                         _ => {
@@ -326,7 +849,6 @@ impl Server {
                         }
                     },
                     'L' => {
-                        ev.load_text();
                         ev_s.send(ev).unwrap();
                     }
                     _ => {}
@@ -335,6 +857,325 @@ impl Server {
             .unwrap();
         Ok(s)
     }
+    // start starts the client name.
+    fn start(&mut self, name: &str) -> Result<()> {
+        let folders = self.instance(name).folders.clone();
+        let mut config = self.server_config(name).clone();
+        if !folders.is_empty() {
+            config.root_uri = Some(folders[0].clone());
+            config.workspace_folders = Some(folders);
+        }
+        let trace = config.trace.unwrap_or_default();
+        let log = match lsp::Log::open(&self.log_path(name), trace) {
+            Ok(log) => Some(log),
+            Err(err) => {
+                self.output = format!("{}: cannot open log: {}", name, err);
+                None
+            }
+        };
+        let (client, msg_id) = (self.start)(name, &config, log)?;
+        self.requests.insert(
+            ClientId::new(name, msg_id),
+            (
+                Initialize::METHOD.into(),
+                Url::parse("file:///").unwrap(),
+                Instant::now(),
+            ),
+        );
+        self.clients.insert(name.to_string(), client);
+        self.down.remove(name);
+        Ok(())
+    }
+    // reload_config reloads the config at path.
+    fn reload_config(&mut self, path: &Path) -> Result<()> {
+        let config = only_servers(read_config(path)?, &self.only_servers)?;
+        self.reload(config)
+    }
+    // reload replaces the config with config and rereads the projects of
+    // project instances. Servers that were removed are stopped, ones whose
+    // command, root or options changed are restarted, and ones whose
+    // settings changed are sent them. Added servers start with their first
+    // window, as usual. Nothing changes if config is bad.
+    fn reload(&mut self, config: TomlConfig) -> Result<()> {
+        let (matchers, language_ids) = compile(&config)?;
+        // Broken projects are left as they are and reported by sync_windows.
+        let mut projects = HashMap::new();
+        for dir in self.instances.values().filter_map(|i| i.project.as_ref()) {
+            if let Ok(project) = find_project(dir) {
+                let project = project.filter(|(d, _)| d == dir).map(|(_, p)| p);
+                projects.insert(dir.clone(), project);
+            }
+        }
+        let old = std::mem::replace(&mut self.config, config);
+        self.projects.clear();
+        self.matchers = matchers;
+        self.language_ids = language_ids;
+        let mut names: Vec<String> = self.instances.keys().cloned().collect();
+        names.sort();
+        for name in names {
+            let i = &self.instances[&name];
+            let old = match &i.config {
+                Some(config) => config.clone(),
+                None => old.servers[&i.server].clone(),
+            };
+            let new = match (self.config.servers.get(&i.server), &i.project) {
+                (Some(server), None) => Some((server.clone(), None)),
+                (Some(server), Some(dir)) => match projects.get(dir) {
+                    Some(project) => project
+                        .as_ref()
+                        .and_then(|p| p.servers.get(&i.server))
+                        .map(|ps| {
+                            project_folders(ps, dir).map(|f| (server.with_project(ps), Some(f)))
+                        })
+                        .transpose()?,
+                    None => continue,
+                },
+                (None, _) => None,
+            };
+            // The server, or the project's config of it, was removed.
+            let (new, folders) = match new {
+                Some(new) => new,
+                None => {
+                    self.stop(&name);
+                    self.instances.remove(&name);
+                    self.down.remove(&name);
+                    self.attempts.remove(&name);
+                    continue;
+                }
+            };
+            let i = self.instance(&name);
+            if let Some(folders) = folders {
+                i.folders = folders;
+                i.config = Some(new.clone());
+            }
+            if old.needs_restart(&new) {
+                if self.clients.contains_key(&name) {
+                    self.stop(&name);
+                    self.attempts.remove(&name);
+                    self.try_start(&name);
+                }
+            } else if old.settings != new.settings && self.initialized(&name) {
+                self.push_settings(&name)?;
+            }
+        }
+        self.sync_windows()
+    }
+    // push_settings sends the client name its settings.
+    fn push_settings(&mut self, name: &str) -> Result<()> {
+        let settings = self.server_config(name).settings.clone();
+        self.send_notification::<DidChangeConfiguration>(
+            &name.to_string(),
+            DidChangeConfigurationParams {
+                settings: settings.unwrap_or_default(),
+            },
+        )
+    }
+    // log_path returns the path of the log of the client name.
+    fn log_path(&self, name: &str) -> PathBuf {
+        self.log_dir.join(format!("{}.log", name.replace('/', "_")))
+    }
+    // instance returns the instance for the client name. Clients of servers
+    // without root markers are named after their server.
+    fn instance(&mut self, name: &str) -> &mut Instance {
+        self.instances
+            .entry(name.to_string())
+            .or_insert_with(|| Instance {
+                server: name.to_string(),
+                folders: vec![],
+                project: None,
+                config: None,
+            })
+    }
+    // server_config returns the configuration of the server the client name
+    // is an instance of.
+    fn server_config(&self, name: &str) -> &ConfigServer {
+        match self.instances.get(name) {
+            Some(Instance {
+                config: Some(config),
+                ..
+            }) => config,
+            Some(i) => &self.config.servers[&i.server],
+            None => &self.config.servers[name],
+        }
+    }
+    // forget removes a client and its outstanding state. Its windows stay
+    // tracked so they can be opened again when it restarts.
+    fn forget(&mut self, name: &str) -> Option<lsp::Client> {
+        self.capabilities.remove(name);
+        self.progress.retain(|(client, _), _| client != name);
+        self.requests.retain(|id, _| id.client_name != name);
+        self.actions.retain(|id, _| id.client_name != name);
+        self.action_addrs
+            .retain(|(_, a)| a.as_ref().is_none_or(|(id, _)| id.client_name != name));
+        self.idle.remove(name);
+        self.clients.remove(name)
+    }
+    // stop shuts down a running client.
+    fn stop(&mut self, name: &str) {
+        if let Some(client) = self.forget(name) {
+            if let Err(err) = client.shutdown(SHUTDOWN_TIMEOUT) {
+                self.output = err.to_string();
+            }
+        }
+    }
+    // shutdown stops all running clients.
+    fn shutdown(&mut self) {
+        let names: Vec<String> = self.clients.keys().cloned().collect();
+        for name in names {
+            self.stop(&name);
+        }
+    }
+    // run_timers restarts the down servers whose backoff has passed at now,
+    // stops the servers that have been idle too long and cancels the
+    // requests that have timed out.
+    fn run_timers(&mut self, now: Instant) {
+        let due: Vec<String> = self
+            .down
+            .iter()
+            .filter(|(_, at)| at.is_some_and(|at| at <= now))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in due {
+            self.try_start(&name);
+        }
+        let idle: Vec<String> = self
+            .idle_deadlines()
+            .filter(|(_, at)| *at <= now)
+            .map(|(name, _)| name)
+            .collect();
+        for name in idle {
+            self.stop(&name);
+        }
+        let expired: Vec<ClientId> = self
+            .request_deadlines()
+            .filter(|(_, at)| *at <= now)
+            .map(|(id, _)| id)
+            .collect();
+        for id in expired {
+            let secs = self.request_timeout(&id.client_name);
+            if let Some((method, _, _)) = self.cancel(&id) {
+                self.output = format!("{}: {} timed out after {}s", id.client_name, method, secs);
+            }
+        }
+    }
+    // request_timeout returns how many seconds the client name has to answer
+    // a request.
+    fn request_timeout(&self, name: &str) -> u64 {
+        self.server_config(name)
+            .request_timeout
+            .unwrap_or(REQUEST_TIMEOUT)
+    }
+    // request_deadlines returns when each pending request times out. The
+    // Initialize request doesn't: the server shows as starting until it
+    // answers.
+    fn request_deadlines(&self) -> impl Iterator<Item = (ClientId, Instant)> + '_ {
+        self.requests
+            .iter()
+            .filter(|(_, (method, _, _))| method != Initialize::METHOD)
+            .map(move |(id, (_, _, sent))| {
+                let secs = self.request_timeout(&id.client_name);
+                (id.clone(), *sent + Duration::from_secs(secs))
+            })
+    }
+    // cancel forgets the pending request id and asks its server to stop
+    // working on it, returning the request.
+    fn cancel(&mut self, id: &ClientId) -> Option<(String, Url, Instant)> {
+        let request = self.requests.remove(id)?;
+        let params = CancelParams {
+            id: NumberOrString::Number(id.msg_id as i32),
+        };
+        if let Err(err) = self.send_notification::<Cancel>(&id.client_name, params) {
+            self.output = err.to_string();
+        }
+        Some(request)
+    }
+    // try_start starts name, scheduling a restart if it fails.
+    fn try_start(&mut self, name: &str) {
+        if let Err(err) = self.start(name) {
+            self.output = format!("{}: {}", name, err);
+            self.schedule_restart(name);
+        }
+    }
+    // idle_deadlines returns when each idle server should be stopped.
+    fn idle_deadlines(&self) -> impl Iterator<Item = (String, Instant)> + '_ {
+        self.idle.iter().filter_map(move |(name, since)| {
+            let secs = self.server_config(name).idle_timeout?;
+            Some((name.clone(), *since + Duration::from_secs(secs)))
+        })
+    }
+    // schedule_restart marks name as down and schedules a restart, backing
+    // off exponentially with each attempt.
+    fn schedule_restart(&mut self, name: &str) {
+        let attempts = self.attempts.entry(name.to_string()).or_default();
+        let backoff = Duration::from_secs(1 << (*attempts).min(6));
+        *attempts += 1;
+        self.down
+            .insert(name.to_string(), Some(Instant::now() + backoff));
+    }
+    // next_timer returns when run_timers next has something to do.
+    fn next_timer(&self) -> Option<Instant> {
+        let idle = self.idle_deadlines().map(|(_, at)| at);
+        let requests = self.request_deadlines().map(|(_, at)| at);
+        self.down
+            .values()
+            .flatten()
+            .copied()
+            .chain(idle)
+            .chain(requests)
+            .min()
+    }
+    fn run_server_cmd(&mut self, name: &str, cmd: &str) -> Result<()> {
+        match cmd {
+            "restart" => {
+                self.stop(name);
+                self.attempts.remove(name);
+                self.start(name)
+            }
+            "stop" => {
+                self.stop(name);
+                self.down.insert(name.to_string(), None);
+                Ok(())
+            }
+            "log" => {
+                let path = self.log_path(name);
+                let path = path.to_string_lossy();
+                let mut w = match self.winid_by_name(&path) {
+                    Some(id) => Win::open(&self.acme, id)?,
+                    None => {
+                        let mut w = Win::new_in(&self.acme)?;
+                        w.name(&path)?;
+                        w
+                    }
+                };
+                w.ctl("get")?;
+                w.ctl("show")
+            }
+            _ => Ok(()),
+        }
+    }
+    // providers returns the initialized clients, out of clients, to send the
+    // file command cmd. Commands whose answers can be merged go to every
+    // client that handles them; others go only to the first, the primary.
+    fn providers(&self, clients: &[String], cmd: &str) -> Vec<String> {
+        let mut v: Vec<String> = clients
+            .iter()
+            .filter(|c| {
+                self.capabilities
+                    .get(*c)
+                    .is_some_and(|caps| has_command(caps, cmd))
+            })
+            .cloned()
+            .collect();
+        if !matches!(cmd, "assist" | "complete") {
+            v.truncate(1);
+        }
+        v
+    }
+    // initialized reports whether the client name is running and has
+    // answered its Initialize request.
+    fn initialized(&self, name: &str) -> bool {
+        self.capabilities.contains_key(name)
+    }
     fn winid_by_name(&self, filename: &str) -> Option<usize> {
         for (name, id) in &self.names {
             if filename == name {
@@ -364,67 +1205,61 @@ impl Server {
         if let Some(hover) = &self.current_hover {
             write!(&mut body, "{}\n\n----\n\n", hover)?;
         }
-        for (_, ds) in &self.diags {
+        for ds in self.diags.values() {
             for d in ds {
-                write!(&mut body, "{}\n", d)?;
+                writeln!(&mut body, "{}", d)?;
             }
-            if ds.len() > 0 {
+            if !ds.is_empty() {
                 body.push('\n');
             }
         }
         self.addr.clear();
         for (file_name, id) in &self.names {
-            self.addr.push((body.len(), *id));
+            self.addr.push((body.chars().count(), *id));
             write!(
                 &mut body,
                 "{}{}\n\t",
                 if *file_name == self.focus { "*" } else { "" },
                 file_name
             )?;
-            let client_name = self.files.get(file_name).unwrap();
-            let caps = match self.capabilities.get(client_name) {
-                Some(v) => v,
-                None => continue,
-            };
-            if caps.code_action_provider.is_some() {
-                body.push_str("[assist] ");
-            }
-            if caps.completion_provider.is_some() {
-                body.push_str("[complete] ");
-            }
-            if caps.definition_provider.is_some() {
-                body.push_str("[definition] ");
-            }
-            if caps.implementation_provider.is_some() {
-                body.push_str("[impl] ");
-            }
-            #[cfg(debug_assertions)]
-            {
-                if caps.code_lens_provider.is_some() {
-                    body.push_str("[lens] ");
+            let clients = &self.files[file_name];
+            for cmd in FILE_COMMANDS {
+                if *cmd == "lens" && !cfg!(debug_assertions) {
+                    continue;
+                }
+                if !self.providers(clients, cmd).is_empty() {
+                    write!(&mut body, "[{}] ", cmd)?;
                 }
-            }
-            if caps.references_provider.is_some() {
-                body.push_str("[references] ");
-            }
-            if caps.document_symbol_provider.is_some() {
-                body.push_str("[symbols] ");
-            }
-            if caps.signature_help_provider.is_some() {
-                body.push_str("[signature] ");
-            }
-            if caps.type_definition_provider.is_some() {
-                body.push_str("[typedef] ");
             }
             body.push('\n');
         }
-        self.addr.push((body.len(), 0));
-        write!(&mut body, "-----\n")?;
+        self.addr.push((body.chars().count(), 0));
+        self.server_addrs.clear();
+        let mut names: Vec<&String> = self.instances.keys().collect();
+        for (_, server) in &self.matchers {
+            if !self.instances.values().any(|i| &i.server == server) {
+                names.push(server);
+            }
+        }
+        names.sort();
+        for name in names {
+            self.server_addrs.push((body.chars().count(), name.clone()));
+            let state = match self.down.get(name) {
+                Some(Some(_)) => "down, restarting",
+                Some(None) => "stopped",
+                None if self.initialized(name) => "running",
+                None if self.clients.contains_key(name) => "starting",
+                None => "not started",
+            };
+            writeln!(&mut body, "{}: {} [restart] [stop] [log]", name, state)?;
+        }
+        self.server_addrs.push((body.chars().count(), "".into()));
+        writeln!(&mut body, "-----")?;
         self.action_addrs.clear();
         for (client_id, actions) in &self.actions {
             for (idx, action) in actions.iter().enumerate() {
                 self.action_addrs
-                    .push((body.len(), (client_id.clone(), idx)));
+                    .push((body.chars().count(), Some((client_id.clone(), idx))));
                 match action {
                     Action::Command(CodeActionOrCommand::Command(cmd)) => {
                         write!(&mut body, "\n[{}]", cmd.title)?;
@@ -446,157 +1281,413 @@ impl Server {
                     }
                 }
             }
-            write!(&mut body, "\n")?;
+            writeln!(&mut body)?;
         }
-        self.action_addrs
-            .push((body.len(), (ClientId::new("", 0), 100000)));
+        self.action_addrs.push((body.chars().count(), None));
         if !self.output.is_empty() {
             write!(&mut body, "\n{}\n", self.output)?;
         }
-        if self.progress.len() > 0 {
+        if !self.progress.is_empty() {
             body.push('\n');
         }
-        for (_, p) in &self.progress {
-            write!(&mut body, "{}\n", p)?;
+        for p in self.progress.values() {
+            writeln!(&mut body, "{}", p)?;
         }
-        if self.requests.len() > 0 {
+        if !self.requests.is_empty() {
             body.push('\n');
         }
-        for (client_id, (method, url)) in &self.requests {
-            write!(
+        let mut requests: Vec<_> = self.requests.iter().collect();
+        requests.sort_by_key(|(id, (_, _, sent))| (*sent, id.msg_id));
+        self.request_addrs.clear();
+        for (client_id, (method, url, sent)) in requests {
+            self.request_addrs
+                .push((body.chars().count(), client_id.clone()));
+            writeln!(
                 &mut body,
-                "{}: {}: {}...\n",
+                "{}: {}: {} ({}s)... [cancel]",
                 client_id.client_name,
                 url.path(),
-                method
+                method,
+                sent.elapsed().as_secs()
             )?;
         }
         if self.body != body {
             self.body = body.clone();
-            self.w.write(File::Addr, &format!(","))?;
+            self.w.write(File::Addr, ",")?;
             self.w.write(File::Data, &body)?;
             self.w.ctl("cleartag\nclean")?;
-            self.w.write(File::Tag, " Get")?;
+            self.w.write(File::Tag, " Get Reload")?;
         }
         Ok(())
     }
     fn sync_windows(&mut self) -> Result<()> {
         let mut ws = HashMap::new();
-        let mut wins = WinInfo::windows()?;
+        // Group windows by name. zerox clones of a window share its ServerWin.
+        let mut wins: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for wi in WinInfo::windows_in(&self.acme)? {
+            wins.entry(wi.name).or_default().push(wi.id);
+        }
         self.names.clear();
-        wins.sort_by(|a, b| a.name.cmp(&b.name));
+        self.clones.clear();
         self.files.clear();
-        for wi in wins {
-            let mut client = None;
-            for (_, c) in self.clients.iter_mut() {
-                if c.files.is_match(&wi.name) {
-                    // Don't open windows for a client that hasn't initialized yet.
-                    if !self.capabilities.contains_key(&c.name) {
-                        continue;
-                    }
-                    self.files.insert(wi.name.clone(), c.name.clone());
-                    client = Some(c);
-                    break;
+        for (name, mut ids) in wins {
+            // A broken .acre.toml leaves only its own files untracked.
+            let clients = match self.clients_for(&name) {
+                Ok(clients) => clients,
+                Err(err) => {
+                    self.output = err.to_string();
+                    continue;
                 }
-            }
-            let client = match client {
-                Some(c) => c,
-                None => continue,
             };
-            self.names.push((wi.name.clone(), wi.id));
-            let w = match self.ws.remove(&wi.id) {
-                Some(w) => w,
-                None => {
-                    let mut fsys = FSYS.lock().unwrap();
-                    let ctl = fsys.open(format!("{}/ctl", wi.id).as_str(), OpenMode::RDWR)?;
-                    let w = Win::open(&mut fsys, wi.id, ctl)?;
-                    // Explicitly drop fsys here to remove its lock to prevent deadlocking if we
-                    // call w.events().
-                    drop(fsys);
-                    let mut sw = ServerWin::new(wi.name, w, client.name.clone())?;
-                    let (version, text) = sw.text()?;
-                    self.send_notification::<DidOpenTextDocument>(
-                        &sw.client,
-                        DidOpenTextDocumentParams {
-                            text_document: TextDocumentItem::new(
-                                sw.url.clone(),
-                                "".to_string(), // lang id
-                                version,
-                                text,
-                            ),
-                        },
-                    )?;
-
-                    sw
+            if clients.is_empty() {
+                continue;
+            }
+            // Start servers when their first window opens.
+            for client in &clients {
+                if !self.clients.contains_key(client) && !self.down.contains_key(client) {
+                    self.try_start(client);
                 }
+            }
+            self.files.insert(name.clone(), clients.clone());
+            ids.sort_unstable();
+            let mut sw = match ids.iter().find_map(|id| self.ws.remove(id)) {
+                Some(sw) => sw,
+                None => match self.take_sw_by_name(&name) {
+                    // The window was closed but a clone of it remains.
+                    Some(mut sw) => {
+                        sw.w = Win::open(&self.acme, ids[0])?;
+                        sw
+                    }
+                    None => ServerWin::new(name.clone(), Win::open(&self.acme, ids[0])?)?,
+                },
             };
-            ws.insert(wi.id, w);
+            // acme doesn't log renames, so notice them here.
+            if sw.name != name {
+                let old = std::mem::take(&mut sw.clients);
+                self.did_close(&sw, &old)?;
+                sw.rename(name.clone())?;
+            }
+            // Open the file in new clients and close it in ones that no
+            // longer handle it.
+            let closed: Vec<String> = sw
+                .clients
+                .iter()
+                .filter(|c| !clients.contains(c))
+                .cloned()
+                .collect();
+            self.did_close(&sw, &closed)?;
+            let opened: Vec<String> = clients
+                .iter()
+                .filter(|c| !sw.clients.contains(c))
+                .cloned()
+                .collect();
+            sw.clients = clients;
+            self.did_open(&mut sw, &opened)?;
+            let wid = sw.w.id();
+            for id in ids {
+                if id != wid {
+                    self.clones.insert(id, wid);
+                }
+            }
+            self.names.push((name, wid));
+            ws.insert(wid, sw);
         }
         // close remaining files
-        let to_close: Vec<(String, TextDocumentIdentifier)> = self
-            .ws
-            .iter()
-            .map(|(_, w)| (w.client.clone(), w.doc_ident()))
-            .collect();
-        for (client_name, text_document) in to_close {
-            self.send_notification::<DidCloseTextDocument>(
-                &client_name,
-                DidCloseTextDocumentParams { text_document },
-            )?;
+        for sw in std::mem::replace(&mut self.ws, ws).values() {
+            self.did_close(sw, &sw.clients)?;
+        }
+        // Note when servers are left without windows.
+        let now = Instant::now();
+        for name in self.clients.keys() {
+            if self.ws.values().any(|sw| sw.clients.contains(name)) {
+                self.idle.remove(name);
+            } else {
+                self.idle.entry(name.clone()).or_insert(now);
+            }
         }
-        self.ws = ws;
         Ok(())
     }
-    fn lsp_msg(&mut self, client_name: String, orig_msg: Vec<u8>) -> Result<()> {
-        let msg: lsp::DeMessage = serde_json::from_slice(&orig_msg)?;
-        if msg.id.is_some() && msg.error.is_some() {
-            self.lsp_error(
-                ClientId::new(client_name, msg.id.unwrap()),
-                msg.error.unwrap(),
-            )
-        } else if msg.id.is_some() && msg.method.is_some() {
-            self.lsp_request(msg)
-        } else if msg.id.is_some() {
-            self.lsp_response(ClientId::new(client_name, msg.id.unwrap()), msg.result)
-        } else if msg.method.is_some() {
-            self.lsp_notification(client_name, msg.method.unwrap(), msg.params)
-        } else {
-            panic!(
-                "unknown message {}",
-                std::str::from_utf8(&orig_msg).unwrap()
-            );
+    // clients_for returns the names of the clients that handle filename,
+    // primary first.
+    fn clients_for(&mut self, filename: &str) -> Result<Vec<String>> {
+        let servers: Vec<String> = self
+            .matchers
+            .iter()
+            .filter(|(files, _)| files.is_match(filename))
+            .map(|(_, server)| server.clone())
+            .collect();
+        let mut clients = vec![];
+        for server in servers {
+            if let Some(client) = self.client_for(server, filename)? {
+                clients.push(client);
+            }
         }
+        Ok(clients)
     }
-    fn lsp_error(&mut self, client_id: ClientId, err: lsp::ResponseError) -> Result<()> {
-        self.requests.remove(&client_id);
-        self.output = format!("{}", err.message);
-        Ok(())
-    }
-    fn lsp_response(
-        &mut self,
-        client_id: ClientId,
-        result: Option<Box<serde_json::value::RawValue>>,
-    ) -> Result<()> {
-        let (typ, url) = self
-            .requests
-            .remove(&client_id)
-            .expect(&format!("expected client id {:?}", client_id));
-        let result = match result {
-            Some(v) => v,
-            None => {
-                self.output = "null".into();
-                return Ok(());
+    // client_for returns the name of the client of server that handles
+    // filename. Files in a project configuring server get the project's
+    // instance. For servers with root markers, this finds the file's
+    // workspace root and picks or creates an instance serving it. None is
+    // returned while that can't be decided yet.
+    fn client_for(&mut self, server: String, filename: &str) -> Result<Option<String>> {
+        let dir = Path::new(filename).parent().unwrap_or(Path::new("/"));
+        if let Some((dir, project)) = self.project(dir)? {
+            if let Some(ps) = project.servers.get(&server) {
+                let name = format!("{}:{}", server, dir.display());
+                if !self.instances.contains_key(&name) {
+                    let config = self.config.servers[&server].with_project(ps);
+                    let folders = project_folders(ps, &dir)?;
+                    self.instances.insert(
+                        name.clone(),
+                        Instance {
+                            server,
+                            folders,
+                            project: Some(dir),
+                            config: Some(config),
+                        },
+                    );
+                }
+                return Ok(Some(name));
             }
-        };
+        }
+        let markers = self.config.servers[&server]
+            .root_markers
+            .clone()
+            .unwrap_or_default();
+        let root_dir = match Path::new(filename)
+            .parent()
+            .and_then(|dir| find_root(dir, &markers))
+        {
+            Some(root) => root,
+            None => {
+                self.instance(&server);
+                return Ok(Some(server));
+            }
+        };
+        let root = dir_uri(&root_dir)?;
+        let mut names: Vec<String> = self
+            .instances
+            .iter()
+            .filter(|(_, i)| i.server == server && i.config.is_none())
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        if let Some(name) = names
+            .iter()
+            .find(|name| self.instances[*name].folders.contains(&root))
+        {
+            return Ok(Some(name.clone()));
+        }
+        for name in &names {
+            if !self.clients.contains_key(name) {
+                // An instance that hasn't started yet can take any root.
+                if self.instances[name].folders.is_empty() {
+                    self.instance(name).folders.push(root);
+                    return Ok(Some(name.clone()));
+                }
+                continue;
+            }
+            let caps = match self.capabilities.get(name) {
+                Some(caps) => caps,
+                // Wait to see if it can take more folders.
+                None => return Ok(None),
+            };
+            let folders = caps
+                .workspace
+                .as_ref()
+                .and_then(|w| w.workspace_folders.as_ref());
+            if folders
+                .is_some_and(|f| f.supported == Some(true) && f.change_notifications.is_some())
+            {
+                self.send_notification::<DidChangeWorkspaceFolders>(
+                    name,
+                    DidChangeWorkspaceFoldersParams {
+                        event: WorkspaceFoldersChangeEvent {
+                            added: vec![WorkspaceFolder {
+                                uri: Url::parse(&root)?,
+                                name: "".to_string(),
+                            }],
+                            removed: vec![],
+                        },
+                    },
+                )?;
+                self.instance(name).folders.push(root);
+                return Ok(Some(name.clone()));
+            }
+        }
+        let name = if names.is_empty() {
+            server.clone()
+        } else {
+            format!("{}:{}", server, root_dir.display())
+        };
+        self.instances.insert(
+            name.clone(),
+            Instance {
+                server,
+                folders: vec![root],
+                project: None,
+                config: None,
+            },
+        );
+        Ok(Some(name))
+    }
+    // project returns the project dir is in, from the cache if it's there.
+    fn project(&mut self, dir: &Path) -> Result<Project> {
+        let project = self
+            .projects
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| find_project(dir).map_err(|err| err.to_string()));
+        match project {
+            Ok(project) => Ok(project.clone()),
+            Err(err) => bail!("{}", err),
+        }
+    }
+    fn take_sw_by_name(&mut self, filename: &str) -> Option<ServerWin> {
+        let wid = self
+            .ws
+            .iter()
+            .find(|(_, sw)| sw.name == filename)
+            .map(|(wid, _)| *wid)?;
+        self.ws.remove(&wid)
+    }
+    // primary returns the id of the window whose ServerWin is shared by the
+    // zerox clone wid.
+    fn primary(&self, wid: usize) -> usize {
+        *self.clones.get(&wid).unwrap_or(&wid)
+    }
+    // did_open opens the file of sw in those of clients that have
+    // initialized.
+    fn did_open(&mut self, sw: &mut ServerWin, clients: &[String]) -> Result<()> {
+        let clients: Vec<&String> = clients.iter().filter(|c| self.initialized(c)).collect();
+        if clients.is_empty() {
+            return Ok(());
+        }
+        let (version, text) = sw.text()?;
+        for client in clients {
+            let server = self.instances.get(client).map_or(client, |i| &i.server);
+            let language_id = self.language_ids[server]
+                .iter()
+                .find(|(re, _)| re.is_match(&sw.name))
+                .map_or_else(|| language_id(&sw.name), |(_, id)| id.as_str());
+            self.send_notification::<DidOpenTextDocument>(
+                client,
+                DidOpenTextDocumentParams {
+                    text_document: TextDocumentItem::new(
+                        sw.url.clone(),
+                        language_id.to_string(),
+                        version,
+                        text.clone(),
+                    ),
+                },
+            )?;
+        }
+        Ok(())
+    }
+    // did_close closes the file of sw in those of clients that have
+    // initialized.
+    fn did_close(&mut self, sw: &ServerWin, clients: &[String]) -> Result<()> {
+        for client in clients {
+            if self.initialized(client) {
+                self.send_notification::<DidCloseTextDocument>(
+                    client,
+                    DidCloseTextDocumentParams {
+                        text_document: sw.doc_ident(),
+                    },
+                )?;
+            }
+        }
+        Ok(())
+    }
+    fn lsp_event(&mut self, client_name: String, ev: lsp::Incoming) -> Result<()> {
+        match ev {
+            lsp::Incoming::Message(msg) => self.lsp_msg(client_name, msg),
+            lsp::Incoming::Error(err) => {
+                self.output = format!("{}: {}", client_name, err);
+                Ok(())
+            }
+            lsp::Incoming::Exit => {
+                self.output = format!("{} exited", client_name);
+                self.forget(&client_name);
+                self.schedule_restart(&client_name);
+                Ok(())
+            }
+        }
+    }
+    fn lsp_msg(&mut self, client_name: String, orig_msg: Vec<u8>) -> Result<()> {
+        let msg: lsp::DeMessage = serde_json::from_slice(&orig_msg)?;
+        match msg {
+            lsp::DeMessage {
+                id: Some(id),
+                error: Some(err),
+                ..
+            } => self.lsp_error(ClientId::new(client_name, id), err),
+            lsp::DeMessage {
+                id: Some(_),
+                method: Some(_),
+                ..
+            } => self.lsp_request(client_name, msg),
+            lsp::DeMessage {
+                id: Some(id),
+                result,
+                ..
+            } => self.lsp_response(ClientId::new(client_name, id), result),
+            lsp::DeMessage {
+                method: Some(method),
+                params,
+                ..
+            } => self.lsp_notification(client_name, method, params),
+            _ => bail!("unknown message {}", String::from_utf8_lossy(&orig_msg)),
+        }
+    }
+    fn lsp_error(&mut self, client_id: ClientId, err: lsp::ResponseError) -> Result<()> {
+        // Ignore errors for requests that were cancelled.
+        if self.requests.remove(&client_id).is_some() {
+            self.output = err.message.to_string();
+        }
+        Ok(())
+    }
+    fn lsp_response(
+        &mut self,
+        client_id: ClientId,
+        result: Option<Box<serde_json::value::RawValue>>,
+    ) -> Result<()> {
+        let (typ, url) = match self.requests.remove(&client_id) {
+            Some((typ, url, _)) => (typ, url),
+            // The request was cancelled.
+            None => return Ok(()),
+        };
+        let result = match result {
+            Some(v) => v,
+            None => {
+                self.output = "null".into();
+                return Ok(());
+            }
+        };
         match typ.as_str() {
             Initialize::METHOD => {
                 let msg = serde_json::from_str::<InitializeResult>(result.get())?;
-                self.send_notification::<Initialized>(
-                    &client_id.client_name,
-                    InitializedParams {},
-                )?;
-                self.capabilities
-                    .insert(client_id.client_name, msg.capabilities.clone());
+                let name = client_id.client_name;
+                self.capabilities.insert(name.clone(), msg.capabilities);
+                self.attempts.remove(&name);
+                self.send_notification::<Initialized>(&name, InitializedParams {})?;
+                if let Some(value) = self.server_config(&name).trace {
+                    self.send_notification::<lsp::SetTrace>(&name, lsp::SetTraceParams { value })?;
+                }
+                if self.server_config(&name).settings.is_some() {
+                    self.push_settings(&name)?;
+                }
+                // Open the windows tracked while the server was down.
+                let wids: Vec<usize> = self
+                    .ws
+                    .iter()
+                    .filter(|(_, sw)| sw.clients.contains(&name))
+                    .map(|(wid, _)| *wid)
+                    .collect();
+                for wid in wids {
+                    let mut sw = self.ws.remove(&wid).unwrap();
+                    self.did_open(&mut sw, std::slice::from_ref(&name))?;
+                    self.ws.insert(wid, sw);
+                }
                 self.sync_windows()?;
             }
             GotoDefinition::METHOD => {
@@ -622,14 +1713,18 @@ impl Server {
                         HoverContents::Markup(mc) => {
                             self.current_hover = Some(mc.value.clone());
                         }
-                        _ => panic!("unknown hover response: {:?}", msg),
+                        HoverContents::Scalar(MarkedString::String(s)) => {
+                            self.current_hover = Some(s.clone());
+                        }
+                        HoverContents::Scalar(MarkedString::LanguageString(s)) => {
+                            self.current_hover = Some(s.value.clone());
+                        }
                     };
                 }
             }
             Completion::METHOD => {
                 let msg = serde_json::from_str::<Option<CompletionResponse>>(result.get())?;
                 if let Some(msg) = msg {
-                    self.actions.clear();
                     let actions = match msg {
                         CompletionResponse::Array(cis) => cis,
                         CompletionResponse::List(cls) => cls.items,
@@ -647,7 +1742,7 @@ impl Server {
                 if let Some(mut msg) = msg {
                     msg.sort_by(cmp_location);
                     let o: Vec<String> = msg.into_iter().map(|x| location_to_plumb(&x)).collect();
-                    if o.len() > 0 {
+                    if !o.is_empty() {
                         self.output = o.join("\n");
                     }
                 }
@@ -658,7 +1753,7 @@ impl Server {
                     let mut o: Vec<String> = vec![];
                     fn add_symbol(
                         o: &mut Vec<String>,
-                        container: &Vec<String>,
+                        container: &[String],
                         name: &String,
                         kind: SymbolKind,
                         loc: &Location,
@@ -674,12 +1769,16 @@ impl Server {
                             kind,
                             location_to_plumb(loc),
                         ));
-                    };
+                    }
                     match msg.clone() {
                         DocumentSymbolResponse::Flat(sis) => {
                             for si in sis {
                                 // Ignore variables in methods.
-                                if si.container_name.as_ref().unwrap_or(&"".to_string()).len() == 0
+                                if si
+                                    .container_name
+                                    .as_ref()
+                                    .unwrap_or(&"".to_string())
+                                    .is_empty()
                                     && si.kind == SymbolKind::Variable
                                 {
                                     continue;
@@ -694,30 +1793,30 @@ impl Server {
                         DocumentSymbolResponse::Nested(mut dss) => {
                             fn process(
                                 url: &Url,
-                                mut o: &mut Vec<String>,
-                                parents: &Vec<String>,
+                                o: &mut Vec<String>,
+                                parents: &[String],
                                 dss: &mut Vec<DocumentSymbol>,
                             ) {
-                                dss.sort_by(|a, b| a.range.start.line.cmp(&b.range.start.line));
+                                dss.sort_by_key(|a| a.range.start.line);
                                 for ds in dss {
                                     add_symbol(
-                                        &mut o,
+                                        o,
                                         parents,
                                         &ds.name,
                                         ds.kind,
                                         &Location::new(url.clone(), ds.range),
                                     );
                                     if let Some(mut children) = ds.children.clone() {
-                                        let mut parents = parents.clone();
+                                        let mut parents = parents.to_vec();
                                         parents.push(ds.name.clone());
                                         process(url, o, &parents, &mut children);
                                     }
                                 }
-                            };
-                            process(&url, &mut o, &vec![], &mut dss);
+                            }
+                            process(&url, &mut o, &[], &mut dss);
                         }
                     }
-                    if o.len() > 0 {
+                    if !o.is_empty() {
                         self.output = o.join("\n");
                     }
                 }
@@ -729,7 +1828,7 @@ impl Server {
                     for sig in &msg.signatures {
                         o.push(sig.label.clone());
                     }
-                    if o.len() > 0 {
+                    if !o.is_empty() {
                         self.output = o.join("\n");
                     }
                 }
@@ -743,9 +1842,9 @@ impl Server {
                             uri: url.clone(),
                             range: lens.range,
                         };
-                        o.push(format!("{}", location_to_plumb(&loc)));
+                        o.push(location_to_plumb(&loc).to_string());
                     }
-                    if o.len() > 0 {
+                    if !o.is_empty() {
                         self.output = o.join("\n");
                     }
                 }
@@ -758,7 +1857,6 @@ impl Server {
                             self.run_action(Action::Command(m))?;
                         }
                     } else {
-                        self.actions.clear();
                         let mut v = vec![];
                         for m in msg.iter().cloned() {
                             v.push(Action::Command(m));
@@ -773,10 +1871,7 @@ impl Server {
                     self.apply_text_edits(&url, InsertTextFormat::PlainText, &msg)?;
                     // Run any on put actions.
                     let actions = self
-                        .config
-                        .servers
-                        .get(&client_id.client_name)
-                        .unwrap()
+                        .server_config(&client_id.client_name)
                         .actions_on_put
                         .clone()
                         .unwrap_or(vec![]);
@@ -815,7 +1910,7 @@ impl Server {
                     goto_definition(&msg)?;
                 }
             }
-            _ => panic!("unrecognized type: {}", typ),
+            _ => bail!("unexpected response to {}", typ),
         }
         Ok(())
     }
@@ -826,12 +1921,21 @@ impl Server {
         params: Option<Box<serde_json::value::RawValue>>,
     ) -> Result<()> {
         match method.as_str() {
+            lsp::LogTrace::METHOD => {
+                let msg: lsp::LogTraceParams = parse_params(params)?;
+                if let Some(log) = self.clients.get(&client_name).and_then(|c| c.log.as_ref()) {
+                    log.write("trace", &msg.message);
+                    if let Some(verbose) = &msg.verbose {
+                        log.write("trace", verbose);
+                    }
+                }
+            }
             LogMessage::METHOD => {
-                let msg: LogMessageParams = serde_json::from_str(params.unwrap().get())?;
+                let msg: LogMessageParams = parse_params(params)?;
                 self.output = format!("[{:?}] {}", msg.typ, msg.message);
             }
             PublishDiagnostics::METHOD => {
-                let msg: PublishDiagnosticsParams = serde_json::from_str(params.unwrap().get())?;
+                let msg: PublishDiagnosticsParams = parse_params(params)?;
                 let mut v = vec![];
                 let path = msg.uri.path();
                 for p in &msg.diagnostics {
@@ -844,20 +1948,21 @@ impl Server {
                         msg,
                     ));
                 }
-                self.diags.insert(path.to_string(), v);
+                self.diags.insert((client_name, path.to_string()), v);
             }
             ShowMessage::METHOD => {
-                let msg: ShowMessageParams = serde_json::from_str(params.unwrap().get())?;
+                let msg: ShowMessageParams = parse_params(params)?;
                 self.output = format!("[{:?}] {}", msg.typ, msg.message);
             }
             Progress::METHOD => {
-                let msg: ProgressParams = serde_json::from_str(params.unwrap().get())?;
+                let msg: ProgressParams = parse_params(params)?;
                 let name = format!("{}-{:?}", client_name, msg.token);
+                let key = (client_name, msg.token.clone());
                 match &msg.value {
                     ProgressParamsValue::WorkDone(value) => match value {
                         WorkDoneProgress::Begin(value) => {
                             self.progress.insert(
-                                name.clone(),
+                                key,
                                 WDProgress::new(
                                     name,
                                     value.percentage,
@@ -867,24 +1972,78 @@ impl Server {
                             );
                         }
                         WorkDoneProgress::Report(value) => {
-                            let p = self.progress.get_mut(&name).unwrap();
-                            p.percentage = value.percentage;
-                            p.message = value.message.clone();
+                            if let Some(p) = self.progress.get_mut(&key) {
+                                p.percentage = value.percentage;
+                                p.message = value.message.clone();
+                            }
                         }
                         WorkDoneProgress::End(_) => {
-                            self.progress.remove(&name);
+                            self.progress.remove(&key);
                         }
                     },
                 }
             }
-            _ => {
-                panic!("unrecognized method: {}", method);
-            }
+            // Servers send extensions, like experimental/serverStatus, and
+            // notifications acre has no use for, like telemetry/event.
+            _ => log!(Debug, "{}: ignoring notification {}", client_name, method),
         }
         Ok(())
     }
-    fn lsp_request(&mut self, msg: lsp::DeMessage) -> Result<()> {
-        println!("unknown request {:?}", msg);
+    fn lsp_request(&mut self, client_name: String, msg: lsp::DeMessage) -> Result<()> {
+        let id = msg.id.unwrap();
+        match msg.method.as_deref() {
+            Some(WorkspaceConfiguration::METHOD) => {
+                let params: ConfigurationParams = parse_params(msg.params)?;
+                let settings = self
+                    .server_config(&client_name)
+                    .settings
+                    .clone()
+                    .unwrap_or_default();
+                let result = params
+                    .items
+                    .iter()
+                    .map(|item| match &item.section {
+                        Some(section) => settings_section(&settings, section),
+                        None => settings.clone(),
+                    })
+                    .collect();
+                if let Some(client) = self.clients.get_mut(&client_name) {
+                    client.respond::<WorkspaceConfiguration>(id, result)?;
+                }
+            }
+            Some(WorkspaceFoldersRequest::METHOD) => {
+                // The folders the client was started with.
+                let config = self.server_config(&client_name);
+                let folders = match self.instances.get(&client_name) {
+                    Some(i) if !i.folders.is_empty() => i.folders.clone(),
+                    _ => config
+                        .workspace_folders
+                        .clone()
+                        .or_else(|| config.root_uri.clone().map(|root| vec![root]))
+                        .unwrap_or_default(),
+                };
+                let folders = folders
+                    .iter()
+                    .map(|uri| {
+                        Ok(WorkspaceFolder {
+                            uri: Url::parse(uri)?,
+                            name: "".to_string(),
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let result = Some(folders).filter(|f| !f.is_empty());
+                if let Some(client) = self.clients.get_mut(&client_name) {
+                    client.respond::<WorkspaceFoldersRequest>(id, result)?;
+                }
+            }
+            method => {
+                log!(Warn, "unknown request {:?}", msg);
+                let message = format!("unsupported request {}", method.unwrap_or_default());
+                if let Some(client) = self.clients.get_mut(&client_name) {
+                    client.respond_error(id, lsp::METHOD_NOT_FOUND, message)?;
+                }
+            }
+        }
         Ok(())
     }
     fn apply_workspace_edit(&mut self, edit: &WorkspaceEdit) -> Result<()> {
@@ -892,7 +2051,7 @@ impl Server {
             match doc_changes {
                 DocumentChanges::Edits(edits) => {
                     for edit in edits {
-                        let text_edits = edit
+                        let text_edits: Vec<TextEdit> = edit
                             .edits
                             .iter()
                             .filter_map(|e| {
@@ -912,12 +2071,12 @@ impl Server {
                         )?;
                     }
                 }
-                _ => panic!("unsupported document_changes {:?}", doc_changes),
+                _ => bail!("unsupported document_changes {:?}", doc_changes),
             }
         }
         if let Some(ref changes) = edit.changes {
             for (url, edits) in changes {
-                self.apply_text_edits(&url, InsertTextFormat::PlainText, &edits)?;
+                self.apply_text_edits(url, InsertTextFormat::PlainText, edits)?;
             }
         }
         Ok(())
@@ -926,7 +2085,7 @@ impl Server {
         &mut self,
         url: &Url,
         format: InsertTextFormat,
-        edits: &Vec<TextEdit>,
+        edits: &[TextEdit],
     ) -> Result<()> {
         if edits.is_empty() {
             return Ok(());
@@ -971,8 +2130,6 @@ impl Server {
         for edit in edits.iter().rev() {
             let soff = offsets.line_to_offset(edit.range.start.line, edit.range.start.character);
             let eoff = offsets.line_to_offset(edit.range.end.line, edit.range.end.character);
-            let addr = format!("#{},#{}", soff, eoff);
-            sw.w.addr(&addr)?;
             match format {
                 InsertTextFormat::Snippet => {
                     lazy_static! {
@@ -980,14 +2137,12 @@ impl Server {
                             Regex::new(r"(\$\{\d+:[[:alpha:]]+\})|(\$0)").unwrap();
                     }
                     let text = &SNIPPET.replace_all(&edit.new_text, "");
-                    sw.w.write(File::Data, text)?;
-                    text.len()
+                    sw.w.replace_range(soff, eoff, text)?;
                 }
                 InsertTextFormat::PlainText => {
-                    sw.w.write(File::Data, &edit.new_text)?;
-                    edit.new_text.len()
+                    sw.w.replace_range(soff, eoff, &edit.new_text)?;
                 }
-            };
+            }
         }
         Ok(())
     }
@@ -995,25 +2150,50 @@ impl Server {
         // Sometimes we are sending a DidChange before a DidOpen. Maybe this is because
         // acme's event log sometimes misses events. Sync the windows just to be sure.
         self.sync_windows()?;
-        let sw = match self.ws.get_mut(&wid) {
+        let capabilities = &self.capabilities;
+        let sw = match self.ws.get_mut(&self.primary(wid)) {
             Some(sw) => sw,
             // Ignore untracked windows.
             None => return Ok(()),
         };
-        let client = sw.client.clone();
+        let clients: Vec<String> = sw
+            .clients
+            .iter()
+            .filter(|c| capabilities.contains_key(*c))
+            .cloned()
+            .collect();
+        if clients.is_empty() {
+            return Ok(());
+        }
         let params = sw.change_params()?;
-        self.send_notification::<DidChangeTextDocument>(&client, params)
+        for client in &clients {
+            self.send_notification::<DidChangeTextDocument>(client, params.clone())?;
+        }
+        Ok(())
     }
     fn set_focus(&mut self, ev: LogEvent) -> Result<()> {
         self.focus = ev.name.clone();
 
         let sw = self.get_sw_by_name(&ev.name)?;
-        let client_name = &sw.client.clone();
+        let clients = sw.clients.clone();
         let url = sw.url.clone();
         let wid = sw.w.id();
         let text_document_position_params = sw.text_doc_pos()?;
-        drop(sw);
         self.did_change(wid)?;
+        let client_name = match self.providers(&clients, "hover").pop() {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+        // The hover for the previous focus is no longer wanted.
+        let hovers: Vec<ClientId> = self
+            .requests
+            .iter()
+            .filter(|(_, (method, _, _))| method == HoverRequest::METHOD)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in hovers {
+            self.cancel(&id);
+        }
         self.send_request::<HoverRequest>(
             &client_name,
             url,
@@ -1027,7 +2207,7 @@ impl Server {
     fn run_event(&mut self, ev: Event, wid: usize) -> Result<()> {
         self.did_change(wid)?;
         let sw = self.ws.get_mut(&wid).unwrap();
-        let client_name = &sw.client.clone();
+        let clients = sw.clients.clone();
         let url = sw.url.clone();
         let text_document_position_params = sw.text_doc_pos()?;
         let text_document_position = text_document_position_params.clone();
@@ -1036,120 +2216,130 @@ impl Server {
             start: text_document_position.position,
             end: text_document_position.position,
         };
-        drop(sw);
-        match ev.text.as_str() {
-            "definition" => {
-                self.send_request::<GotoDefinition>(
-                    client_name,
-                    url,
-                    GotoDefinitionParams {
-                        text_document_position_params,
-                        work_done_progress_params,
-                        partial_result_params,
-                    },
-                )?;
-            }
-            "complete" => {
-                self.send_request::<Completion>(
-                    client_name,
-                    url,
-                    CompletionParams {
-                        text_document_position,
-                        work_done_progress_params,
-                        partial_result_params,
-                        context: Some(CompletionContext {
-                            trigger_kind: CompletionTriggerKind::Invoked,
-                            trigger_character: None,
-                        }),
-                    },
-                )?;
-            }
-            "references" => {
-                self.send_request::<References>(
-                    client_name,
-                    url,
-                    ReferenceParams {
-                        text_document_position,
-                        work_done_progress_params,
-                        partial_result_params,
-                        context: ReferenceContext {
-                            include_declaration: true,
+        let targets = self.providers(&clients, &ev.text);
+        if matches!(ev.text.as_str(), "assist" | "complete") {
+            // The answers from every server are gathered.
+            self.actions.clear();
+        }
+        for client_name in &targets {
+            let url = url.clone();
+            let text_document_position_params = text_document_position_params.clone();
+            let text_document_position = text_document_position.clone();
+            let text_document = text_document.clone();
+            match ev.text.as_str() {
+                "definition" => {
+                    self.send_request::<GotoDefinition>(
+                        client_name,
+                        url,
+                        GotoDefinitionParams {
+                            text_document_position_params,
+                            work_done_progress_params,
+                            partial_result_params,
                         },
-                    },
-                )?;
-            }
-            "symbols" => {
-                self.send_request::<DocumentSymbolRequest>(
-                    client_name,
-                    url,
-                    DocumentSymbolParams {
-                        text_document,
-                        work_done_progress_params,
-                        partial_result_params,
-                    },
-                )?;
-            }
-            "signature" => {
-                self.send_request::<SignatureHelpRequest>(
-                    client_name,
-                    url,
-                    SignatureHelpParams {
-                        context: None,
-                        text_document_position_params,
-                        work_done_progress_params,
-                    },
-                )?;
-            }
-            "lens" => {
-                self.send_request::<CodeLensRequest>(
-                    client_name,
-                    url,
-                    CodeLensParams {
-                        text_document,
-                        work_done_progress_params,
-                        partial_result_params,
-                    },
-                )?;
-            }
-            "assist" => {
-                self.send_request::<CodeActionRequest>(
-                    client_name,
-                    url,
-                    CodeActionParams {
-                        text_document,
-                        range,
-                        context: CodeActionContext {
-                            diagnostics: vec![],
-                            only: None,
+                    )?;
+                }
+                "complete" => {
+                    self.send_request::<Completion>(
+                        client_name,
+                        url,
+                        CompletionParams {
+                            text_document_position,
+                            work_done_progress_params,
+                            partial_result_params,
+                            context: Some(CompletionContext {
+                                trigger_kind: CompletionTriggerKind::Invoked,
+                                trigger_character: None,
+                            }),
                         },
-                        work_done_progress_params,
-                        partial_result_params,
-                    },
-                )?;
-            }
-            "impl" => {
-                self.send_request::<GotoImplementation>(
-                    client_name,
-                    url,
-                    GotoImplementationParams {
-                        text_document_position_params,
-                        work_done_progress_params,
-                        partial_result_params,
-                    },
-                )?;
-            }
-            "typedef" => {
-                self.send_request::<GotoTypeDefinition>(
-                    client_name,
-                    url,
-                    GotoDefinitionParams {
-                        text_document_position_params,
-                        work_done_progress_params,
-                        partial_result_params,
-                    },
-                )?;
+                    )?;
+                }
+                "references" => {
+                    self.send_request::<References>(
+                        client_name,
+                        url,
+                        ReferenceParams {
+                            text_document_position,
+                            work_done_progress_params,
+                            partial_result_params,
+                            context: ReferenceContext {
+                                include_declaration: true,
+                            },
+                        },
+                    )?;
+                }
+                "symbols" => {
+                    self.send_request::<DocumentSymbolRequest>(
+                        client_name,
+                        url,
+                        DocumentSymbolParams {
+                            text_document,
+                            work_done_progress_params,
+                            partial_result_params,
+                        },
+                    )?;
+                }
+                "signature" => {
+                    self.send_request::<SignatureHelpRequest>(
+                        client_name,
+                        url,
+                        SignatureHelpParams {
+                            context: None,
+                            text_document_position_params,
+                            work_done_progress_params,
+                        },
+                    )?;
+                }
+                "lens" => {
+                    self.send_request::<CodeLensRequest>(
+                        client_name,
+                        url,
+                        CodeLensParams {
+                            text_document,
+                            work_done_progress_params,
+                            partial_result_params,
+                        },
+                    )?;
+                }
+                "assist" => {
+                    self.send_request::<CodeActionRequest>(
+                        client_name,
+                        url,
+                        CodeActionParams {
+                            text_document,
+                            range,
+                            context: CodeActionContext {
+                                diagnostics: vec![],
+                                only: None,
+                            },
+                            work_done_progress_params,
+                            partial_result_params,
+                        },
+                    )?;
+                }
+                "impl" => {
+                    self.send_request::<GotoImplementation>(
+                        client_name,
+                        url,
+                        GotoImplementationParams {
+                            text_document_position_params,
+                            work_done_progress_params,
+                            partial_result_params,
+                        },
+                    )?;
+                }
+                "typedef" => {
+                    self.send_request::<GotoTypeDefinition>(
+                        client_name,
+                        url,
+                        GotoDefinitionParams {
+                            text_document_position_params,
+                            work_done_progress_params,
+                            partial_result_params,
+                        },
+                    )?;
+                }
+                _ => {}
             }
-            _ => {}
         }
         Ok(())
     }
@@ -1159,10 +2349,15 @@ impl Server {
         url: Url,
         params: R::Params,
     ) -> Result<usize> {
-        let client = self.clients.get_mut(client_name).unwrap();
+        let client = match self.clients.get_mut(client_name) {
+            Some(c) => c,
+            None => bail!("{} is not running", client_name),
+        };
         let msg_id = client.send::<R>(params)?;
-        self.requests
-            .insert(ClientId::new(client_name, msg_id), (R::METHOD.into(), url));
+        self.requests.insert(
+            ClientId::new(client_name, msg_id),
+            (R::METHOD.into(), url, Instant::now()),
+        );
         Ok(msg_id)
     }
     fn send_notification<N: notification::Notification>(
@@ -1170,7 +2365,10 @@ impl Server {
         client_name: &String,
         params: N::Params,
     ) -> Result<()> {
-        let client = self.clients.get_mut(client_name).unwrap();
+        let client = match self.clients.get_mut(client_name) {
+            Some(c) => c,
+            None => bail!("{} is not running", client_name),
+        };
         client.notify::<N>(params)
     }
     fn run_code_action(&mut self, client_id: ClientId, idx: usize) -> Result<()> {
@@ -1191,7 +2389,7 @@ impl Server {
                         match serde_json::from_value::<ArgWorkspaceEdit>(arg) {
                             Ok(v) => self.apply_workspace_edit(&v.workspace_edit)?,
                             Err(err) => {
-                                println!("json err {}", err);
+                                log!(Warn, "json err {}", err);
                                 continue;
                             }
                         }
@@ -1210,10 +2408,10 @@ impl Server {
                 if let Some(edit) = item.text_edit.clone() {
                     match edit {
                         CompletionTextEdit::Edit(edit) => {
-                            return self.apply_text_edits(&url, format, &vec![edit])
+                            return self.apply_text_edits(&url, format, &[edit])
                         }
                         CompletionTextEdit::InsertAndReplace(_) => {
-                            println!("InsertAndReplace not supported");
+                            log!(Warn, "InsertAndReplace not supported");
                             return Ok(());
                         }
                     }
@@ -1223,7 +2421,7 @@ impl Server {
         }
         Ok(())
     }
-    fn run_cmd(&mut self, ev: Event) -> Result<()> {
+    fn run_cmd(&mut self, mut ev: Event) -> Result<()> {
         match ev.c2 {
             'x' | 'X' => match ev.text.as_str() {
                 "Get" => {
@@ -1233,11 +2431,22 @@ impl Server {
                     self.diags.clear();
                     self.current_hover = None;
                 }
+                "Reload" => {
+                    if let Some(path) = self.config_path.clone() {
+                        // A bad config leaves everything running as it was.
+                        self.output = match self.reload_config(&path) {
+                            Ok(()) => format!("reloaded {}", path.display()),
+                            Err(err) => format!("{}: {}", path.display(), err),
+                        };
+                    }
+                }
                 _ => {
                     panic!("unexpected");
                 }
             },
             'L' => {
+                // Long selections arrive without their text.
+                ev.load_text(&mut self.w)?;
                 {
                     let mut wid = 0;
                     for (pos, id) in self.addr.iter().rev() {
@@ -1251,15 +2460,36 @@ impl Server {
                     }
                 }
                 {
-                    let mut cid: Option<(ClientId, usize)> = None;
-                    for (pos, (client_id, idx)) in self.action_addrs.iter().rev() {
-                        if (*pos as u32) < ev.q0 && client_id.msg_id != 0 {
-                            cid = Some((client_id.clone(), *idx));
+                    let mut server = None;
+                    for (pos, name) in self.server_addrs.iter().rev() {
+                        if (*pos as u32) < ev.q0 {
+                            server = Some(name.clone());
                             break;
                         }
                     }
-                    if let Some((cid, idx)) = cid {
-                        return self.run_code_action(cid, idx);
+                    if let Some(name) = server.filter(|n| !n.is_empty()) {
+                        return self.run_server_cmd(&name, &ev.text);
+                    }
+                }
+                let action = self
+                    .action_addrs
+                    .iter()
+                    .rev()
+                    .find(|(pos, _)| (*pos as u32) < ev.q0)
+                    .and_then(|(_, action)| action.clone());
+                if let Some((cid, idx)) = action {
+                    return self.run_code_action(cid, idx);
+                }
+                if ev.text == "cancel" {
+                    let id = self
+                        .request_addrs
+                        .iter()
+                        .rev()
+                        .find(|(pos, _)| (*pos as u32) < ev.q0)
+                        .map(|(_, id)| id.clone());
+                    if let Some(id) = id {
+                        self.cancel(&id);
+                        return Ok(());
                     }
                 }
                 return plumb_location(ev.text);
@@ -1270,46 +2500,50 @@ impl Server {
     }
     fn cmd_put(&mut self, id: usize) -> Result<()> {
         self.did_change(id)?;
-        let sw = if let Some(sw) = self.ws.get(&id) {
+        let sw = if let Some(sw) = self.ws.get(&self.primary(id)) {
             sw
         } else {
-            // Ignore unknown ids (untracked files, zerox, etc.).
+            // Ignore unknown ids (untracked files, etc.).
             return Ok(());
         };
-        let client_name = &sw.client.clone();
+        let clients = sw.clients.clone();
         let text_document = sw.doc_ident();
         let url = sw.url.clone();
-        drop(sw);
-        self.send_notification::<DidSaveTextDocument>(
-            client_name,
-            DidSaveTextDocumentParams {
-                text_document: text_document.clone(),
-                text: None,
-            },
-        )?;
-        let capabilities = self.capabilities.get(client_name).unwrap();
-        if self
-            .config
-            .servers
-            .get(client_name)
-            .unwrap()
-            .format_on_put
-            .unwrap_or(true)
-            && capabilities.document_formatting_provider.is_some()
-        {
-            self.send_request::<Formatting>(
+        let filename = sw.name.clone();
+        for client_name in &clients {
+            if !self.initialized(client_name) {
+                continue;
+            }
+            self.send_notification::<DidSaveTextDocument>(
                 client_name,
+                DidSaveTextDocumentParams {
+                    text_document: text_document.clone(),
+                    text: None,
+                },
+            )?;
+        }
+        let client_name = match self.providers(&clients, "format").pop() {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+        let config = self.server_config(&client_name);
+        if config.format_on_put.unwrap_or(true) {
+            let options = config.formatting.clone().unwrap_or_default();
+            let w = &mut self.ws.get_mut(&self.primary(id)).unwrap().w;
+            // Don't format with the wrong options, but don't stop either.
+            let options = match options.options(&filename, || acme_tabstop(w)) {
+                Ok(options) => options,
+                Err(err) => {
+                    self.output = format!("{}: {}", filename, err);
+                    return Ok(());
+                }
+            };
+            self.send_request::<Formatting>(
+                &client_name,
                 url,
                 DocumentFormattingParams {
                     text_document,
-                    options: FormattingOptions {
-                        tab_size: 4,
-                        insert_spaces: false,
-                        properties: HashMap::new(),
-                        trim_trailing_whitespace: Some(true),
-                        insert_final_newline: Some(true),
-                        trim_final_newlines: Some(true),
-                    },
+                    options,
                     work_done_progress_params: WorkDoneProgressParams {
                         work_done_token: None,
                     },
@@ -1318,7 +2552,15 @@ impl Server {
         }
         Ok(())
     }
+    // wait handles events until acme goes away, then shuts the servers down.
+    // They are shut down however it returns, so that they are never killed
+    // in the middle of writing.
     fn wait(&mut self) -> Result<()> {
+        let r = self.run();
+        self.shutdown();
+        r
+    }
+    fn run(&mut self) -> Result<()> {
         let (sync_s, sync_r) = bounded(1);
 
         self.sync_windows()?;
@@ -1330,11 +2572,8 @@ impl Server {
         let sel_ev_r = sel.recv(&self.ev_r);
         let sel_err_r = sel.recv(&self.err_r);
         let sel_sync_r = sel.recv(&sync_r);
-        let mut clients = HashMap::new();
-
-        for (name, c) in &self.clients {
-            clients.insert(sel.recv(&c.msg_r), (c.msg_r.clone(), name.to_string()));
-        }
+        let timer_r = never::<Instant>();
+        let sel_timer_r = sel.recv(&timer_r);
         drop(sel);
 
         loop {
@@ -1345,30 +2584,39 @@ impl Server {
             sel.recv(&self.ev_r);
             sel.recv(&self.err_r);
             sel.recv(&sync_r);
-            for (_, c) in &self.clients {
-                sel.recv(&c.msg_r);
+            let timer_r = match self.next_timer() {
+                Some(at) => after(at.saturating_duration_since(Instant::now())),
+                None => never(),
+            };
+            sel.recv(&timer_r);
+            // Clients come and go, so map their indexes each time.
+            let mut clients = HashMap::new();
+            for c in self.clients.values() {
+                clients.insert(sel.recv(&c.msg_r), (c.msg_r.clone(), c.name.clone()));
             }
             let index = sel.ready();
 
             match index {
                 _ if index == sel_log_r => {
                     let msg = self.log_r.recv();
-                    println!("log {:?}", msg);
+                    log!(Debug, "log {:?}", msg);
                     match msg {
-                        Ok(ev) => match ev.op.as_str() {
-                            "focus" => {
+                        Ok(ev) => match ev.op {
+                            LogOp::Focus => {
                                 let _ = self.set_focus(ev);
                             }
-                            "put" => {
+                            LogOp::Put => {
                                 self.cmd_put(ev.id)?;
                                 no_sync = true;
                             }
-                            "new" | "del" => {
-                                self.sync_windows()?;
+                            LogOp::Get => {
+                                // The file was reloaded from disk.
+                                self.did_change(ev.id)?;
                             }
-                            _ => {
-                                panic!("unknown event op {:?}", ev);
+                            LogOp::New | LogOp::Zerox | LogOp::Del => {
+                                self.sync_windows()?;
                             }
+                            LogOp::Other(_) => {}
                         },
                         Err(_) => {
                             break;
@@ -1377,7 +2625,7 @@ impl Server {
                 }
                 _ if index == sel_ev_r => {
                     let msg = self.ev_r.recv();
-                    println!("ev {:?}", msg);
+                    log!(Debug, "ev {:?}", msg);
                     match msg {
                         Ok(ev) => {
                             self.run_cmd(ev)?;
@@ -1389,7 +2637,7 @@ impl Server {
                 }
                 _ if index == sel_err_r => {
                     let msg = self.err_r.recv();
-                    println!("err {:?}", msg);
+                    log!(Error, "err {:?}", msg);
                     match msg {
                         Ok(_) => {
                             break;
@@ -1404,10 +2652,18 @@ impl Server {
                     let _ = sync_r.recv();
                     self.sync()?;
                 }
+                _ if index == sel_timer_r => {
+                    let _ = timer_r.recv();
+                    self.run_timers(Instant::now());
+                }
                 _ => {
-                    let (ch, name) = clients.get(&index).unwrap();
-                    let msg = ch.recv()?;
-                    self.lsp_msg(name.to_string(), msg)?;
+                    let (ch, name) = clients.remove(&index).unwrap();
+                    // The reader sends Exit last, so a closed channel means
+                    // the same thing.
+                    let ev = ch.recv().unwrap_or(lsp::Incoming::Exit);
+                    if let Err(err) = self.lsp_event(name.clone(), ev) {
+                        self.output = format!("{}: {}", name, err);
+                    }
                 }
             };
 
@@ -1428,6 +2684,116 @@ impl Drop for Server {
     }
 }
 
+// FILE_COMMANDS are the commands listed under each file.
+const FILE_COMMANDS: &[&str] = &[
+    "assist",
+    "complete",
+    "definition",
+    "impl",
+    "lens",
+    "references",
+    "symbols",
+    "signature",
+    "typedef",
+];
+
+// has_command reports whether a server with caps handles the file command
+// cmd. Hover and format are sent by acre itself.
+fn has_command(caps: &ServerCapabilities, cmd: &str) -> bool {
+    match cmd {
+        "assist" => caps.code_action_provider.is_some(),
+        "complete" => caps.completion_provider.is_some(),
+        "definition" => caps.definition_provider.is_some(),
+        "impl" => caps.implementation_provider.is_some(),
+        "lens" => caps.code_lens_provider.is_some(),
+        "references" => caps.references_provider.is_some(),
+        "symbols" => caps.document_symbol_provider.is_some(),
+        "signature" => caps.signature_help_provider.is_some(),
+        "typedef" => caps.type_definition_provider.is_some(),
+        "hover" => caps.hover_provider.is_some(),
+        "format" => caps.document_formatting_provider.is_some(),
+        _ => false,
+    }
+}
+
+// find_root returns the closest of dir and its parents containing one of
+// markers.
+fn find_root(dir: &Path, markers: &[String]) -> Option<PathBuf> {
+    dir.ancestors()
+        .find(|d| markers.iter().any(|m| d.join(m).exists()))
+        .map(|d| d.to_path_buf())
+}
+
+// dir_uri returns the URI of the directory dir.
+fn dir_uri(dir: &Path) -> Result<String> {
+    match Url::from_directory_path(dir) {
+        Ok(url) => Ok(url.to_string()),
+        Err(_) => bail!("bad directory {}", dir.display()),
+    }
+}
+
+// parse_params parses the params of a message from a server.
+fn parse_params<T: serde::de::DeserializeOwned>(
+    params: Option<Box<serde_json::value::RawValue>>,
+) -> Result<T> {
+    match params {
+        Some(params) => Ok(serde_json::from_str(params.get())?),
+        None => bail!("missing params"),
+    }
+}
+
+// settings_section returns the settings for section, which may be a
+// dotted path into them, or null if there are none.
+fn settings_section(settings: &Value, section: &str) -> Value {
+    if let Some(v) = settings.get(section) {
+        return v.clone();
+    }
+    section
+        .split('.')
+        .try_fold(settings, |v, key| v.get(key))
+        .cloned()
+        .unwrap_or_default()
+}
+
+// compile returns the (files regex, server name) matchers of config, primary
+// servers first, and each server's language id overrides.
+fn compile(config: &TomlConfig) -> Result<(Matchers, HashMap<String, Matchers>)> {
+    let mut matchers = vec![];
+    let mut language_ids = HashMap::new();
+    for (name, server) in &config.servers {
+        matchers.push((Regex::new(&server.files)?, name.clone()));
+        let mut ids = vec![];
+        for (files, id) in server.language_ids.iter().flatten() {
+            ids.push((Regex::new(files)?, id.clone()));
+        }
+        language_ids.insert(name.clone(), ids);
+    }
+    matchers.sort_by_key(|(_, name)| (config.servers[name].primary != Some(true), name.clone()));
+    Ok((matchers, language_ids))
+}
+
+// Project is a project configuration and the directory it's in.
+type Project = Option<(PathBuf, ProjectConfig)>;
+
+// find_project returns the nearest project configuration in dir or its
+// ancestors.
+fn find_project(dir: &Path) -> Result<Project> {
+    for d in dir.ancestors() {
+        let path = d.join(PROJECT_TOML);
+        if !path.is_file() {
+            continue;
+        }
+        let project = std::fs::read_to_string(&path)
+            .map_err(Error::from)
+            .and_then(|s| Ok(toml::from_str(&s)?));
+        return match project {
+            Ok(project) => Ok(Some((d.to_path_buf(), project))),
+            Err(err) => bail!("{}: {}", path.display(), err),
+        };
+    }
+    Ok(None)
+}
+
 fn goto_definition(goto: &GotoDefinitionResponse) -> Result<()> {
     match goto {
         GotoDefinitionResponse::Array(locs) => match locs.len() {
@@ -1458,7 +2824,7 @@ fn plumb_location(loc: String) -> Result<()> {
         typ: "text".to_string(),
         data: loc.into(),
     };
-    return msg.send(f);
+    msg.send(f)
 }
 
 fn format_pct(pct: Option<u32>) -> String {
@@ -1472,21 +2838,21 @@ fn cmp_location(a: &Location, b: &Location) -> Ordering {
     if a.uri != b.uri {
         return a.uri.as_str().cmp(b.uri.as_str());
     }
-    return cmp_range(&a.range, &b.range);
+    cmp_range(&a.range, &b.range)
 }
 
 fn cmp_range(a: &Range, b: &Range) -> Ordering {
     if a.start != b.start {
         return cmp_position(&a.start, &b.start);
     }
-    return cmp_position(&a.end, &b.end);
+    cmp_position(&a.end, &b.end)
 }
 
 fn cmp_position(a: &Position, b: &Position) -> Ordering {
     if a.line != b.line {
         return a.line.cmp(&b.line);
     }
-    return a.character.cmp(&b.character);
+    a.character.cmp(&b.character)
 }
 
 #[allow(non_upper_case_globals)]
@@ -1497,3 +2863,1128 @@ const work_done_progress_params: WorkDoneProgressParams = WorkDoneProgressParams
 const partial_result_params: PartialResultParams = PartialResultParams {
     partial_result_token: None,
 };
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use plan9::fakeacme::FakeAcme;
+    use serde_json::json;
+
+    use crate::fakelsp::{self, FakeServer, Step};
+    use crate::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    // server returns a Server whose only language server, "fake", plays
+    // script and handles .rs files.
+    fn server(acme: &FakeAcme, script: Vec<Step>) -> (Server, FakeServer) {
+        let (mut s, fakes) = servers(acme, vec![script]);
+        s.start("fake").unwrap();
+        (s, fakes.recv().unwrap())
+    }
+
+    // servers is like server, but "fake" isn't started until its first
+    // window opens. Each time it starts it plays the next of scripts, and
+    // the servers are sent on the returned channel as they start.
+    fn servers(acme: &FakeAcme, scripts: Vec<Vec<Step>>) -> (Server, Receiver<FakeServer>) {
+        let config: TomlConfig = toml::from_str("[servers.fake]\nfiles = '\\.rs$'\n").unwrap();
+        let fsys = Arc::new(Mutex::new(acme.mount().unwrap()));
+        let (fakes_s, fakes_r) = crossbeam_channel::unbounded();
+        let mut scripts = scripts.into_iter();
+        let start: Starter = Box::new(move |name, config, log| {
+            let script = match scripts.next() {
+                Some(script) => script,
+                None => bail!("no script left"),
+            };
+            let (transport, fake) = fakelsp::serve(script);
+            fakes_s.send(fake)?;
+            connect_client(name, config, transport, log)
+        });
+        let mut s = Server::with_starter(config, fsys, start).unwrap();
+        s.log_dir = log_dir();
+        (s, fakes_r)
+    }
+
+    // log_dir returns a new directory for a test's server logs.
+    fn log_dir() -> PathBuf {
+        static N: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let n = N.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        std::env::temp_dir().join(format!("acre-test.{}.{}", std::process::id(), n))
+    }
+
+    fn initialize(capabilities: Value) -> Step {
+        Step::Respond("initialize", json!({ "capabilities": capabilities }))
+    }
+
+    // recv handles the next message from the language server.
+    fn recv(s: &mut Server) -> Result<()> {
+        recv_from(s, "fake")
+    }
+
+    // recv_from handles the next message from the client name.
+    fn recv_from(s: &mut Server, name: &str) -> Result<()> {
+        let msg = s.clients[name].msg_r.recv_timeout(TIMEOUT)?;
+        s.lsp_event(name.to_string(), msg)
+    }
+
+    // click clicks on [cmd] in the acre window.
+    fn click(s: &mut Server, acme: &FakeAcme, cmd: &str) {
+        s.sync().unwrap();
+        let body = acme.body(s.w.id());
+        let q0 = body[..body.find(&format!("[{}]", cmd)).unwrap()]
+            .chars()
+            .count()
+            + 1;
+        acme.look(s.w.id(), q0, q0 + cmd.chars().count());
+        let ev = s.ev_r.recv_timeout(TIMEOUT).unwrap();
+        s.run_cmd(ev).unwrap();
+    }
+
+    fn range(l0: u32, c0: u32, l1: u32, c1: u32) -> Value {
+        json!({
+            "start": {"line": l0, "character": c0},
+            "end": {"line": l1, "character": c1},
+        })
+    }
+
+    #[test]
+    fn parse_flags() {
+        let parse = |args: &[&str]| crate::parse_flags(args.iter().map(|a| a.to_string()));
+        assert_eq!(parse(&[]).unwrap(), Flags::default());
+        assert_eq!(
+            parse(&[
+                "--config",
+                "/tmp/acre.toml",
+                "--namespace=/tmp/ns",
+                "--window-name",
+                "acre-go",
+                "--log-level=debug",
+                "--server",
+                "gopls",
+                "--server=rls",
+            ])
+            .unwrap(),
+            Flags {
+                check: false,
+                config: Some(PathBuf::from("/tmp/acre.toml")),
+                namespace: Some("/tmp/ns".to_string()),
+                window_name: Some("acre-go".to_string()),
+                log_level: Some(LogLevel::Debug),
+                servers: vec!["gopls".to_string(), "rls".to_string()],
+            }
+        );
+        assert!(parse(&["check"]).unwrap().check);
+        let err = |args: &[&str]| parse(args).unwrap_err().to_string();
+        assert_eq!(err(&["--config"]), "--config needs a value");
+        assert_eq!(err(&["--log-level", "loud"]), "unknown log level \"loud\"");
+        assert!(err(&["-v"]).starts_with("unknown argument \"-v\"\nusage: acre"));
+
+        let config = || -> TomlConfig {
+            toml::from_str("[servers.a]\nfiles = 'a'\n[servers.b]\nfiles = 'b'\n").unwrap()
+        };
+        let names = |config: TomlConfig| {
+            let mut names: Vec<String> = config.servers.into_keys().collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(only_servers(config(), &[]).unwrap()), vec!["a", "b"]);
+        let only = vec!["b".to_string()];
+        assert_eq!(names(only_servers(config(), &only).unwrap()), vec!["b"]);
+        let only = vec!["c".to_string()];
+        assert!(only_servers(config(), &only).is_err());
+    }
+
+    #[test]
+    fn language_id() {
+        assert_eq!(crate::language_id("/src/main.rs"), "rust");
+        assert_eq!(crate::language_id("/src/lib.C"), "c");
+        assert_eq!(crate::language_id("/src/lib.cc"), "cpp");
+        assert_eq!(crate::language_id("/src/app.tsx"), "typescriptreact");
+        assert_eq!(crate::language_id("/src/Makefile"), "makefile");
+        assert_eq!(crate::language_id("/src/README"), "plaintext");
+        assert_eq!(crate::language_id("/src/.vimrc"), "plaintext");
+    }
+
+    #[test]
+    fn expand() {
+        std::env::set_var("ACRE_TEST_DIR", "/opt/acre");
+        let home = std::env::var("HOME").unwrap_or_default();
+        assert_eq!(crate::expand("~"), home);
+        assert_eq!(crate::expand("~/bin/gopls"), format!("{}/bin/gopls", home));
+        assert_eq!(crate::expand("a~b"), "a~b");
+        assert_eq!(crate::expand("$ACRE_TEST_DIR/bin"), "/opt/acre/bin");
+        assert_eq!(crate::expand("${ACRE_TEST_DIR}x"), "/opt/acrex");
+        assert_eq!(crate::expand("-remote=$ACRE_TEST_UNSET"), "-remote=");
+    }
+
+    #[test]
+    fn sync_windows() {
+        let acme = FakeAcme::new();
+        let (mut s, fake) = server(
+            &acme,
+            vec![
+                initialize(json!({"definitionProvider": true})),
+                Step::Expect("initialized"),
+                Step::Expect("textDocument/didOpen"),
+                Step::Expect("textDocument/didClose"),
+                Step::Expect("textDocument/didOpen"),
+                Step::Expect("textDocument/didClose"),
+            ],
+        );
+        let id = acme.new_window("/a.rs", "fn main() {}\n");
+        acme.new_window("/b.txt", "");
+        // Windows are synced once the server has initialized.
+        recv(&mut s).unwrap();
+        assert_eq!(s.names, vec![("/a.rs".to_string(), id)]);
+        s.sync().unwrap();
+        assert!(acme.body(s.w.id()).starts_with("/a.rs\n\t[definition] \n"));
+
+        let z = acme.zerox(id);
+        s.sync_windows().unwrap();
+        assert_eq!(s.primary(z), id);
+        assert_eq!(s.ws.len(), 1);
+
+        acme.rename(id, "/c.rs");
+        s.sync_windows().unwrap();
+
+        // Closing the original window leaves the clone tracking the file.
+        acme.del(id);
+        s.sync_windows().unwrap();
+        assert_eq!(s.names, vec![("/c.rs".to_string(), z)]);
+        acme.del(z);
+        s.sync_windows().unwrap();
+        assert!(s.names.is_empty());
+
+        fake.wait();
+        let opened = fake.received("textDocument/didOpen");
+        assert_eq!(opened[0]["textDocument"]["text"], "fn main() {}\n");
+        assert_eq!(opened[1]["textDocument"]["uri"], "file:///c.rs");
+        let closed = fake.received("textDocument/didClose");
+        assert_eq!(closed[0]["textDocument"]["uri"], "file:///a.rs");
+        assert_eq!(closed[1]["textDocument"]["uri"], "file:///c.rs");
+    }
+
+    #[test]
+    fn apply_text_edits() {
+        let acme = FakeAcme::new();
+        let (mut s, _fake) = server(&acme, vec![initialize(json!({}))]);
+        let id = acme.new_window("/a.rs", "fn main() {\n}\n");
+        recv(&mut s).unwrap();
+        let url = Url::parse("file:///a.rs").unwrap();
+        let edit = |l0, c0, l1, c1, text: &str| {
+            TextEdit::new(
+                Range::new(Position::new(l0, c0), Position::new(l1, c1)),
+                text.to_string(),
+            )
+        };
+        s.apply_text_edits(
+            &url,
+            InsertTextFormat::PlainText,
+            &[edit(0, 3, 0, 7, "start"), edit(1, 0, 1, 0, "\t1;\n")],
+        )
+        .unwrap();
+        assert_eq!(acme.body(id), "fn start() {\n\t1;\n}\n");
+
+        // A full replacement is applied as a diff.
+        s.apply_text_edits(
+            &url,
+            InsertTextFormat::PlainText,
+            &[edit(0, 0, 3, 0, "fn start() {\n\t2;\n}\n")],
+        )
+        .unwrap();
+        assert_eq!(acme.body(id), "fn start() {\n\t2;\n}\n");
+
+        // Snippet placeholders are dropped.
+        s.apply_text_edits(
+            &url,
+            InsertTextFormat::Snippet,
+            &[edit(1, 1, 1, 2, "f(${1:arg})$0")],
+        )
+        .unwrap();
+        assert_eq!(acme.body(id), "fn start() {\n\tf();\n}\n");
+    }
+
+    #[test]
+    fn run_cmd() {
+        let acme = FakeAcme::new();
+        let (mut s, fake) = server(
+            &acme,
+            vec![
+                initialize(json!({"definitionProvider": true})),
+                Step::Respond("textDocument/definition", json!(null)),
+            ],
+        );
+        recv(&mut s).unwrap();
+        let acre = s.w.id();
+
+        // Get syncs windows.
+        let id = acme.new_window("/a.rs", "fn main() {}\n");
+        acme.execute(acre, "Get");
+        let ev = s.ev_r.recv_timeout(TIMEOUT).unwrap();
+        s.run_cmd(ev).unwrap();
+        assert_eq!(s.names, vec![("/a.rs".to_string(), id)]);
+
+        // Other commands are handed back to acme.
+        acme.execute(acre, "Snarf");
+        let start = Instant::now();
+        while acme.written_events(acre).is_empty() {
+            assert!(start.elapsed() < TIMEOUT);
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(acme.written_events(acre), vec!["Mx0 5 \n"]);
+
+        // Clicking a file's command sends it to its server.
+        acme.set_dot(id, 3, 3);
+        click(&mut s, &acme, "definition");
+        recv(&mut s).unwrap();
+        fake.wait();
+        let params = &fake.received("textDocument/definition")[0];
+        assert_eq!(params["position"], json!({"line": 0, "character": 3}));
+        assert!(s.requests.is_empty());
+    }
+
+    #[test]
+    fn lsp_response() {
+        let acme = FakeAcme::new();
+        let loc = |uri: &str, line| json!({"uri": uri, "range": range(line, 0, line, 1)});
+        let (mut s, fake) = server(
+            &acme,
+            vec![
+                initialize(json!({
+                    "hoverProvider": true,
+                    "completionProvider": {},
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "documentSymbolProvider": true,
+                })),
+                Step::Respond(
+                    "textDocument/hover",
+                    json!({"contents": {"kind": "markdown", "value": "fn main()"}}),
+                ),
+                Step::Respond(
+                    "textDocument/completion",
+                    json!({"isIncomplete": false, "items": [{"label": "foo", "detail": "fn foo()"}]}),
+                ),
+                Step::Respond(
+                    "textDocument/references",
+                    json!([loc("file:///b.rs", 3), loc("file:///a.rs", 1)]),
+                ),
+                Step::Respond(
+                    "textDocument/documentSymbol",
+                    json!([{
+                        "name": "main",
+                        "kind": 12,
+                        "range": range(0, 0, 2, 1),
+                        "selectionRange": range(0, 3, 0, 7),
+                        "children": [{
+                            "name": "x",
+                            "kind": 13,
+                            "range": range(1, 4, 1, 5),
+                            "selectionRange": range(1, 4, 1, 5),
+                        }],
+                    }]),
+                ),
+                Step::Fail("textDocument/definition", "no definition found"),
+            ],
+        );
+        let id = acme.new_window("/a.rs", "fn main() {\n\tlet x = 1;\n}\n");
+        recv(&mut s).unwrap();
+
+        s.set_focus(LogEvent {
+            id,
+            op: LogOp::Focus,
+            name: "/a.rs".to_string(),
+        })
+        .unwrap();
+        recv(&mut s).unwrap();
+        assert_eq!(s.current_hover.as_deref(), Some("fn main()"));
+
+        click(&mut s, &acme, "complete");
+        recv(&mut s).unwrap();
+        s.sync().unwrap();
+        assert!(acme.body(s.w.id()).contains("\n[insert] foo: fn foo()\n"));
+
+        click(&mut s, &acme, "references");
+        recv(&mut s).unwrap();
+        assert_eq!(s.output, "/a.rs:2\n/b.rs:4");
+
+        click(&mut s, &acme, "symbols");
+        recv(&mut s).unwrap();
+        assert_eq!(
+            s.output,
+            "main (Function): /a.rs:1\nmain::x (Variable): /a.rs:2"
+        );
+
+        click(&mut s, &acme, "definition");
+        recv(&mut s).unwrap();
+        assert_eq!(s.output, "no definition found");
+        assert!(s.requests.is_empty());
+        fake.wait();
+    }
+
+    #[test]
+    fn lsp_notification() {
+        let acme = FakeAcme::new();
+        let progress = |value: Value| json!({"token": "t", "value": value});
+        let (mut s, fake) = server(
+            &acme,
+            vec![
+                initialize(json!({})),
+                Step::Notify(
+                    "textDocument/publishDiagnostics",
+                    json!({
+                        "uri": "file:///a.rs",
+                        "diagnostics": [
+                            {"range": range(1, 0, 1, 1), "severity": 2, "message": "unused\nmore"},
+                            {"range": range(0, 0, 0, 1), "message": "broken"},
+                        ],
+                    }),
+                ),
+                Step::Notify("window/showMessage", json!({"type": 3, "message": "hello"})),
+                Step::Notify(
+                    "$/progress",
+                    progress(json!({"kind": "begin", "title": "indexing", "percentage": 10})),
+                ),
+                Step::Notify(
+                    "$/progress",
+                    progress(json!({"kind": "report", "message": "src", "percentage": 50})),
+                ),
+                Step::Notify("$/progress", progress(json!({"kind": "end"}))),
+                Step::Notify("experimental/serverStatus", json!({"health": "ok"})),
+                Step::Notify("window/showMessage", json!(null)),
+                Step::Raw("Content-Length: 2\r\n\r\n{}"),
+                Step::Request("workspace/applyEdit", json!({"edit": {"changes": {}}})),
+                Step::Expect("response"),
+                Step::Raw("Content-Length: 3\r\n\r\n{]}"),
+                Step::Raw("garbage\r\n\r\n"),
+                Step::Close,
+            ],
+        );
+        recv(&mut s).unwrap();
+
+        recv(&mut s).unwrap();
+        assert_eq!(
+            s.diags[&("fake".to_string(), "/a.rs".to_string())],
+            vec!["/a.rs:2: [Warning] unused", "/a.rs:1: [Error] broken"]
+        );
+        recv(&mut s).unwrap();
+        assert_eq!(s.output, "[Info] hello");
+
+        recv(&mut s).unwrap();
+        recv(&mut s).unwrap();
+        s.sync().unwrap();
+        assert!(acme.body(s.w.id()).contains("[50%] fake-"));
+        assert!(acme.body(s.w.id()).contains(": src (indexing)\n"));
+        recv(&mut s).unwrap();
+        assert!(s.progress.is_empty());
+
+        // Unknown notifications are ignored, and bad messages are errors.
+        recv(&mut s).unwrap();
+        assert_eq!(recv(&mut s).unwrap_err().to_string(), "missing params");
+        assert_eq!(recv(&mut s).unwrap_err().to_string(), "unknown message {}");
+
+        // Requests from the server that acre doesn't support are refused.
+        recv(&mut s).unwrap();
+        // A frame that isn't JSON is an error, and one with a bad header is
+        // reported without ending the connection.
+        assert!(recv(&mut s).is_err());
+        recv(&mut s).unwrap();
+        assert_eq!(s.output, "fake: malformed header \"garbage\"");
+        fake.wait();
+        assert_eq!(
+            fake.received("response"),
+            vec![json!({
+                "jsonrpc": "2.0",
+                "id": 1000,
+                "error": {"code": -32601, "message": "unsupported request workspace/applyEdit"},
+            })]
+        );
+
+        // The server going away marks it as down, leaving the progress of
+        // servers whose names it prefixes.
+        s.progress.insert(
+            ("fake-two".to_string(), NumberOrString::Number(1)),
+            WDProgress::new("fake-two-1".to_string(), None, None, None),
+        );
+        recv(&mut s).unwrap();
+        assert_eq!(s.output, "fake exited");
+        assert_eq!(s.progress.len(), 1);
+        assert!(s.clients.is_empty());
+        assert!(s.next_timer().is_some());
+    }
+
+    #[test]
+    fn restart() {
+        let acme = FakeAcme::new();
+        let id = acme.new_window("/a.rs", "fn main() {}\n");
+        let opened = |v: i32| {
+            json!({"textDocument": {
+                "uri": "file:///a.rs",
+                "languageId": "rust",
+                "version": v,
+                "text": "fn main() {}\n",
+            }})
+        };
+        let (mut s, fakes) = servers(
+            &acme,
+            vec![
+                vec![
+                    initialize(json!({})),
+                    Step::Expect("textDocument/didOpen"),
+                    Step::Close,
+                ],
+                vec![
+                    initialize(json!({})),
+                    Step::Expect("textDocument/didOpen"),
+                    Step::Respond("shutdown", json!(null)),
+                    Step::Expect("exit"),
+                ],
+                vec![initialize(json!({}))],
+            ],
+        );
+        s.sync_windows().unwrap();
+        let fake = fakes.recv().unwrap();
+        recv(&mut s).unwrap();
+        fake.wait();
+        assert_eq!(fake.received("textDocument/didOpen"), vec![opened(2)]);
+
+        // A crashed server is shown as down and restarted after a backoff.
+        recv(&mut s).unwrap();
+        s.sync().unwrap();
+        assert!(acme
+            .body(s.w.id())
+            .contains("fake: down, restarting [restart] [stop] [log]\n"));
+        assert_eq!(s.ws[&id].clients, vec!["fake"]);
+        s.run_timers(Instant::now());
+        assert!(s.clients.is_empty());
+        s.run_timers(Instant::now() + Duration::from_secs(2));
+        let fake = fakes.recv().unwrap();
+        recv(&mut s).unwrap();
+        assert!(s.attempts.is_empty());
+        s.sync().unwrap();
+        assert!(acme.body(s.w.id()).contains("fake: running [restart]"));
+
+        // Stopping a server shuts it down cleanly.
+        click(&mut s, &acme, "stop");
+        fake.wait();
+        // Its windows were opened again with newer versions.
+        assert_eq!(fake.received("textDocument/didOpen"), vec![opened(3)]);
+        assert!(s.clients.is_empty());
+        assert_eq!(s.next_timer(), None);
+        s.sync().unwrap();
+        assert!(acme.body(s.w.id()).contains("fake: stopped [restart]"));
+
+        click(&mut s, &acme, "restart");
+        fakes.recv().unwrap();
+        recv(&mut s).unwrap();
+        assert!(s.initialized("fake"));
+    }
+
+    #[test]
+    fn roots() {
+        let dir = std::env::temp_dir().join(format!("acre-roots-{}", std::process::id()));
+        for p in &["p1/src", "p#2", "p3"] {
+            std::fs::create_dir_all(dir.join(p)).unwrap();
+        }
+        for p in &["p1", "p#2", "p3"] {
+            std::fs::write(dir.join(p).join("Cargo.toml"), "").unwrap();
+        }
+        let markers = vec!["Cargo.toml".to_string()];
+        assert_eq!(
+            find_root(&dir.join("p1/src"), &markers),
+            Some(dir.join("p1"))
+        );
+        assert_eq!(find_root(&dir, &markers), None);
+
+        let path = |p: &str| dir.join(p).to_str().unwrap().to_string();
+        let uri = |p: &str| Url::from_directory_path(dir.join(p)).unwrap().to_string();
+        assert!(uri("p#2").ends_with("/p%232/"));
+        let acme = FakeAcme::new();
+        let folders = json!({"supported": true, "changeNotifications": true});
+        let (mut s, fakes) = servers(
+            &acme,
+            vec![
+                vec![
+                    initialize(json!({"workspace": {"workspaceFolders": folders}})),
+                    Step::Expect("workspace/didChangeWorkspaceFolders"),
+                    Step::Request("workspace/workspaceFolders", json!(null)),
+                    Step::Expect("response"),
+                ],
+                vec![initialize(json!({}))],
+            ],
+        );
+        s.config.servers.get_mut("fake").unwrap().root_markers = Some(markers);
+        acme.new_window(&path("p1/src/a.rs"), "");
+        s.sync_windows().unwrap();
+        let fake = fakes.recv().unwrap();
+        recv(&mut s).unwrap();
+
+        // A server that takes workspace folders is sent new roots.
+        acme.new_window(&path("p#2/b.rs"), "");
+        s.sync_windows().unwrap();
+        recv(&mut s).unwrap();
+        fake.wait();
+        assert_eq!(fake.received("initialize")[0]["rootUri"], uri("p1"));
+        let params = &fake.received("workspace/didChangeWorkspaceFolders")[0];
+        assert_eq!(params["event"]["added"][0]["uri"], uri("p#2"));
+        assert_eq!(s.instances["fake"].folders, vec![uri("p1"), uri("p#2")]);
+        assert_eq!(s.files[&path("p#2/b.rs")], vec!["fake"]);
+        // The server can ask for them too.
+        let response = &fake.received("response")[0];
+        assert_eq!(
+            response["result"],
+            json!([{"uri": uri("p1"), "name": ""}, {"uri": uri("p#2"), "name": ""}])
+        );
+
+        // Other servers get an instance per root.
+        s.capabilities.get_mut("fake").unwrap().workspace = None;
+        acme.new_window(&path("p3/c.rs"), "");
+        s.sync_windows().unwrap();
+        let fake = fakes.recv().unwrap();
+        fake.wait();
+        assert_eq!(fake.received("initialize")[0]["rootUri"], uri("p3"));
+        let name = format!("fake:{}", path("p3"));
+        assert!(s.clients.contains_key(&name));
+        assert_eq!(s.files[&path("p3/c.rs")], vec![name.clone()]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn multiple_servers() {
+        let acme = FakeAcme::new();
+        let config: TomlConfig = toml::from_str(
+            "[servers.fake]\nfiles = '\\.rs$'\nprimary = true\n[servers.lint]\nfiles = '\\.rs$'\nlanguage_ids = { '^/a' = 'lint' }\n",
+        )
+        .unwrap();
+        let caps = json!({"definitionProvider": true, "completionProvider": {}});
+        let diags = |msg: &str| json!({"uri": "file:///a.rs", "diagnostics": [{"range": range(0, 0, 0, 1), "message": msg}]});
+        let mut scripts = HashMap::new();
+        scripts.insert(
+            "fake",
+            vec![
+                initialize(caps.clone()),
+                Step::Notify("textDocument/publishDiagnostics", diags("broken")),
+                Step::Respond("textDocument/completion", json!([{"label": "foo"}])),
+                Step::Respond("textDocument/definition", json!(null)),
+            ],
+        );
+        scripts.insert(
+            "lint",
+            vec![
+                initialize(caps),
+                Step::Notify("textDocument/publishDiagnostics", diags("ugly")),
+                Step::Respond("textDocument/completion", json!([{"label": "bar"}])),
+            ],
+        );
+        let (fakes_s, fakes_r) = crossbeam_channel::unbounded();
+        let start: Starter = Box::new(move |name, config, log| {
+            let (transport, fake) = fakelsp::serve(scripts.remove(name).unwrap());
+            fakes_s.send((name.to_string(), fake))?;
+            connect_client(name, config, transport, log)
+        });
+        let fsys = Arc::new(Mutex::new(acme.mount().unwrap()));
+        let mut s = Server::with_starter(config, fsys, start).unwrap();
+        s.log_dir = log_dir();
+        let id = acme.new_window("/a.rs", "fn main() {}\n");
+        s.sync_windows().unwrap();
+        let fakes: HashMap<String, FakeServer> = fakes_r.try_iter().collect();
+        for name in &["fake", "lint"] {
+            recv_from(&mut s, name).unwrap();
+            recv_from(&mut s, name).unwrap();
+        }
+        assert_eq!(s.ws[&id].clients, vec!["fake", "lint"]);
+        s.sync().unwrap();
+        let body = acme.body(s.w.id());
+        assert!(body.contains("/a.rs:1: [Error] broken\n"));
+        assert!(body.contains("/a.rs:1: [Error] ugly\n"));
+
+        // Completions are merged.
+        click(&mut s, &acme, "complete");
+        recv_from(&mut s, "fake").unwrap();
+        recv_from(&mut s, "lint").unwrap();
+        s.sync().unwrap();
+        let body = acme.body(s.w.id());
+        assert!(body.contains("[insert] foo:"));
+        assert!(body.contains("[insert] bar:"));
+
+        // Definitions come from the primary server.
+        click(&mut s, &acme, "definition");
+        recv_from(&mut s, "fake").unwrap();
+        for fake in fakes.values() {
+            fake.wait();
+        }
+        assert!(fakes["lint"].received("textDocument/definition").is_empty());
+        let opened = |name: &str| fakes[name].received("textDocument/didOpen");
+        assert_eq!(opened("fake")[0]["textDocument"]["languageId"], "rust");
+        assert_eq!(opened("lint").len(), 1);
+        assert_eq!(opened("lint")[0]["textDocument"]["languageId"], "lint");
+    }
+
+    #[test]
+    fn lazy_start() {
+        let acme = FakeAcme::new();
+        let (mut s, fakes) = servers(
+            &acme,
+            vec![
+                vec![
+                    initialize(json!({})),
+                    Step::Respond("shutdown", json!(null)),
+                    Step::Expect("exit"),
+                ],
+                vec![initialize(json!({}))],
+            ],
+        );
+        s.config.servers.get_mut("fake").unwrap().idle_timeout = Some(10);
+        acme.new_window("/b.txt", "");
+        s.sync_windows().unwrap();
+        s.sync().unwrap();
+        assert!(s.clients.is_empty());
+        assert!(acme.body(s.w.id()).contains("fake: not started [restart]"));
+
+        // The server starts with its first window.
+        let id = acme.new_window("/a.rs", "");
+        s.sync_windows().unwrap();
+        let fake = fakes.recv().unwrap();
+        recv(&mut s).unwrap();
+        assert_eq!(s.next_timer(), None);
+
+        // And stops once it has been idle for idle_timeout.
+        acme.del(id);
+        s.sync_windows().unwrap();
+        assert!(s.next_timer().is_some());
+        s.run_timers(Instant::now());
+        assert!(s.initialized("fake"));
+        s.run_timers(Instant::now() + Duration::from_secs(11));
+        fake.wait();
+        assert!(s.clients.is_empty());
+
+        acme.new_window("/a.rs", "");
+        s.sync_windows().unwrap();
+        fakes.recv().unwrap();
+        recv(&mut s).unwrap();
+        assert!(s.initialized("fake"));
+    }
+
+    #[test]
+    fn log() {
+        let acme = FakeAcme::new();
+        let (mut s, fakes) = servers(
+            &acme,
+            vec![vec![
+                initialize(json!({})),
+                Step::Expect("$/setTrace"),
+                Step::Notify("$/logTrace", json!({"message": "thinking"})),
+            ]],
+        );
+        s.config.servers.get_mut("fake").unwrap().trace = Some(TraceOption::Verbose);
+        s.start("fake").unwrap();
+        let fake = fakes.recv().unwrap();
+        recv(&mut s).unwrap();
+        recv(&mut s).unwrap();
+        fake.wait();
+        assert_eq!(
+            fake.received("$/setTrace"),
+            vec![json!({"value": "verbose"})]
+        );
+
+        // The log opens in a window.
+        click(&mut s, &acme, "log");
+        let path = s.log_path("fake");
+        let id = acme.find(&path.to_string_lossy()).unwrap();
+        let body = acme.body(id);
+        assert!(body.contains(r#" --> {"jsonrpc":"2.0","id":1,"method":"initialize""#));
+        assert!(body.contains(" <-- {"));
+        assert!(body.contains(" trace thinking\n"));
+        std::fs::remove_dir_all(&s.log_dir).unwrap();
+    }
+
+    #[test]
+    fn cancel() {
+        let acme = FakeAcme::new();
+        let (mut s, fake) = server(
+            &acme,
+            vec![
+                initialize(json!({
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                })),
+                Step::Expect("textDocument/hover"),
+                Step::Expect("$/cancelRequest"),
+                Step::Respond(
+                    "textDocument/hover",
+                    json!({"contents": {"kind": "plaintext", "value": "second"}}),
+                ),
+                Step::Expect("textDocument/definition"),
+                Step::Expect("$/cancelRequest"),
+                Step::Expect("textDocument/references"),
+                Step::Expect("$/cancelRequest"),
+            ],
+        );
+        let id = acme.new_window("/a.rs", "fn main() {}\n");
+        recv(&mut s).unwrap();
+
+        // A new focus cancels the hover for the old one.
+        let focus = || LogEvent {
+            id,
+            op: LogOp::Focus,
+            name: "/a.rs".to_string(),
+        };
+        s.set_focus(focus()).unwrap();
+        s.set_focus(focus()).unwrap();
+        recv(&mut s).unwrap();
+        assert_eq!(s.current_hover.as_deref(), Some("second"));
+
+        // Requests time out.
+        click(&mut s, &acme, "definition");
+        assert_eq!(
+            s.next_timer(),
+            Some(s.requests.values().next().unwrap().2 + Duration::from_secs(30))
+        );
+        s.run_timers(Instant::now() + Duration::from_secs(31));
+        assert_eq!(
+            s.output,
+            "fake: textDocument/definition timed out after 30s"
+        );
+        assert!(s.requests.is_empty());
+
+        // And can be cancelled by hand, even with actions listed above them.
+        let fix = Action::Command(CodeActionOrCommand::Command(Command {
+            title: "fix".to_string(),
+            command: "fix".to_string(),
+            arguments: None,
+        }));
+        s.actions.insert(ClientId::new("fake", 100), vec![fix]);
+        click(&mut s, &acme, "references");
+        s.sync().unwrap();
+        assert!(acme
+            .body(s.w.id())
+            .contains("\nfake: /a.rs: textDocument/references (0s)... [cancel]\n"));
+        click(&mut s, &acme, "cancel");
+        assert!(s.requests.is_empty());
+        assert_eq!(s.actions.len(), 1);
+        fake.wait();
+        assert_eq!(
+            fake.received("$/cancelRequest"),
+            vec![json!({"id": 2}), json!({"id": 4}), json!({"id": 5})]
+        );
+    }
+
+    #[test]
+    fn projects() {
+        let dir = std::env::temp_dir().join(format!("acre-projects-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("p1/src")).unwrap();
+        std::fs::write(
+            dir.join("p1").join(PROJECT_TOML),
+            "[servers.fake]\nformat_on_put = false\noptions = { a = { b = 2 } }\n",
+        )
+        .unwrap();
+        let path = |p: &str| dir.join(p).to_str().unwrap().to_string();
+        let acme = FakeAcme::new();
+        let (mut s, fakes) = servers(
+            &acme,
+            vec![vec![initialize(json!({}))], vec![initialize(json!({}))]],
+        );
+        let global = s.config.servers.get_mut("fake").unwrap();
+        global.options = Some(json!({"a": {"b": 1, "c": 1}}));
+
+        // Files in a project get an instance with its settings.
+        acme.new_window(&path("p1/src/a.rs"), "");
+        s.sync_windows().unwrap();
+        let fake = fakes.recv().unwrap();
+        fake.wait();
+        let name = format!("fake:{}", path("p1"));
+        assert_eq!(s.files[&path("p1/src/a.rs")], vec![name.clone()]);
+        let params = &fake.received("initialize")[0];
+        assert_eq!(
+            params["rootUri"],
+            Url::from_directory_path(dir.join("p1"))
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(
+            params["initializationOptions"],
+            json!({"a": {"b": 2, "c": 1}})
+        );
+        assert_eq!(s.server_config(&name).format_on_put, Some(false));
+
+        // Other files use the global settings.
+        acme.new_window(&path("b.rs"), "");
+        s.sync_windows().unwrap();
+        let fake = fakes.recv().unwrap();
+        fake.wait();
+        assert_eq!(s.files[&path("b.rs")], vec!["fake"]);
+        let params = &fake.received("initialize")[0];
+        assert_eq!(
+            params["initializationOptions"],
+            json!({"a": {"b": 1, "c": 1}})
+        );
+        assert_eq!(s.server_config("fake").format_on_put, None);
+
+        // Projects are read again on Reload, and broken ones are reported
+        // without affecting other files.
+        std::fs::write(dir.join("p1").join(PROJECT_TOML), "servers = 1").unwrap();
+        let err = find_project(&dir.join("p1/src")).err().unwrap();
+        assert!(err.to_string().starts_with(&path("p1/.acre.toml")));
+        s.sync_windows().unwrap();
+        assert!(s.files.contains_key(&path("p1/src/a.rs")));
+        s.reload(s.config.clone()).unwrap();
+        assert!(s.output.starts_with(&path("p1/.acre.toml")));
+        assert!(!s.files.contains_key(&path("p1/src/a.rs")));
+        assert_eq!(s.files[&path("b.rs")], vec!["fake"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reload() {
+        let acme = FakeAcme::new();
+        let (mut s, fakes) = servers(
+            &acme,
+            vec![
+                vec![
+                    initialize(json!({})),
+                    Step::Expect("workspace/didChangeConfiguration"),
+                    Step::Respond("shutdown", json!(null)),
+                    Step::Expect("exit"),
+                ],
+                vec![
+                    initialize(json!({})),
+                    Step::Respond("shutdown", json!(null)),
+                    Step::Expect("exit"),
+                ],
+                vec![
+                    initialize(json!({})),
+                    Step::Respond("shutdown", json!(null)),
+                    Step::Expect("exit"),
+                ],
+                vec![initialize(json!({}))],
+                vec![
+                    initialize(json!({})),
+                    Step::Expect("workspace/didChangeConfiguration"),
+                    Step::Expect("workspace/didChangeConfiguration"),
+                    Step::Respond("shutdown", json!(null)),
+                    Step::Expect("exit"),
+                ],
+                vec![initialize(json!({}))],
+            ],
+        );
+        let path = std::env::temp_dir().join(format!("acre-reload.{}.toml", std::process::id()));
+        s.config_path = Some(path.clone());
+        acme.new_window("/a.rs", "");
+        s.sync_windows().unwrap();
+        let fake = fakes.recv().unwrap();
+        recv(&mut s).unwrap();
+        let reload = |s: &mut Server, config: &str| {
+            std::fs::write(&path, config).unwrap();
+            acme.execute(s.w.id(), "Reload");
+            let ev = s.ev_r.recv_timeout(TIMEOUT).unwrap();
+            s.run_cmd(ev).unwrap();
+        };
+
+        // New settings are sent to running servers.
+        let config = "[servers.fake]\nfiles = '\\.rs$'\n[servers.fake.settings]\na = 1\n";
+        reload(&mut s, config);
+        assert_eq!(s.output, format!("reloaded {}", path.display()));
+
+        // Servers whose options or command changed are restarted.
+        let config = config.replace(
+            "[servers.fake.settings]",
+            "options = {}\n[servers.fake.settings]",
+        );
+        reload(&mut s, &config);
+        fake.wait();
+        assert_eq!(
+            fake.received("workspace/didChangeConfiguration"),
+            vec![json!({"settings": {"a": 1}})]
+        );
+        let fake = fakes.recv().unwrap();
+        recv(&mut s).unwrap();
+        let config = config.replace("options = {}", "args = ['-v']\noptions = {}");
+        reload(&mut s, &config);
+        fake.wait();
+        let fake = fakes.recv().unwrap();
+        recv(&mut s).unwrap();
+        assert!(s.initialized("fake"));
+
+        // Bad configs are reported and change nothing.
+        for bad in &["[servers.fake", "[servers.fake]\nfiles = '('\n"] {
+            reload(&mut s, bad);
+            assert!(s.output.starts_with(&format!("{}: ", path.display())));
+            assert!(s.initialized("fake"));
+        }
+        s.only_servers = vec!["missing".to_string()];
+        reload(&mut s, &config);
+        assert!(s.output.starts_with(&format!("{}: ", path.display())));
+        s.only_servers = vec![];
+        assert_eq!(s.config.servers["fake"].args, Some(vec!["-v".to_string()]));
+
+        // Removed servers are stopped, and added ones start.
+        reload(&mut s, "[servers.lint]\nfiles = '\\.rs$'\n");
+        fake.wait();
+        assert!(!s.clients.contains_key("fake"));
+        fakes.recv().unwrap();
+        recv_from(&mut s, "lint").unwrap();
+        assert_eq!(s.files["/a.rs"], vec!["lint"]);
+
+        // Project files are read again too.
+        let config = "[servers.lint]\nfiles = '\\.rs$'\n";
+        let dir = std::env::temp_dir().join(format!("acre-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let project = dir.join(PROJECT_TOML);
+        std::fs::write(&project, "[servers.lint]\nsettings = { a = 1 }\n").unwrap();
+        acme.new_window(dir.join("b.rs").to_str().unwrap(), "");
+        s.sync_windows().unwrap();
+        let fake = fakes.recv().unwrap();
+        let name = format!("lint:{}", dir.display());
+        recv_from(&mut s, &name).unwrap();
+        std::fs::write(&project, "[servers.lint]\nsettings = { a = 2 }\n").unwrap();
+        reload(&mut s, config);
+        assert_eq!(s.server_config(&name).settings, Some(json!({"a": 2})));
+        std::fs::write(
+            &project,
+            "[servers.lint]\noptions = {}\nsettings = { a = 2 }\n",
+        )
+        .unwrap();
+        reload(&mut s, config);
+        fake.wait();
+        assert_eq!(
+            fake.received("workspace/didChangeConfiguration"),
+            vec![json!({"settings": {"a": 1}}), json!({"settings": {"a": 2}})]
+        );
+        fakes.recv().unwrap();
+        recv_from(&mut s, &name).unwrap();
+        assert!(s.initialized(&name));
+        assert!(s.initialized("lint"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn settings() {
+        let acme = FakeAcme::new();
+        let (mut s, fakes) = servers(
+            &acme,
+            vec![vec![
+                initialize(json!({})),
+                Step::Expect("workspace/didChangeConfiguration"),
+                Step::Request(
+                    "workspace/configuration",
+                    json!({"items": [
+                        {"section": "gopls"},
+                        {"section": "gopls.ui"},
+                        {"section": "pyright"},
+                        {},
+                    ]}),
+                ),
+                Step::Expect("response"),
+            ]],
+        );
+        s.config.servers.get_mut("fake").unwrap().settings =
+            Some(json!({"gopls": {"ui": {"codelenses": true}}}));
+        s.start("fake").unwrap();
+        let fake = fakes.recv().unwrap();
+        recv(&mut s).unwrap();
+        recv(&mut s).unwrap();
+        fake.wait();
+        let settings = json!({"gopls": {"ui": {"codelenses": true}}});
+        assert_eq!(
+            fake.received("workspace/didChangeConfiguration"),
+            vec![json!({ "settings": settings })]
+        );
+        assert_eq!(
+            fake.received("response")[0]["result"],
+            json!([settings["gopls"], settings["gopls"]["ui"], null, settings])
+        );
+    }
+
+    #[test]
+    fn formatting() {
+        let acme = FakeAcme::new();
+        let (mut s, fake) = server(
+            &acme,
+            vec![
+                initialize(json!({"documentFormattingProvider": true})),
+                Step::Respond("textDocument/formatting", json!(null)),
+                Step::Respond("textDocument/formatting", json!(null)),
+                Step::Respond("textDocument/formatting", json!(null)),
+            ],
+        );
+        s.config.servers.get_mut("fake").unwrap().formatting = Some(
+            toml::from_str("tab_size = 2\ninsert_spaces = true\nfiles = { '^/b' = { tab_size = 8, trim_final_newlines = false } }\n")
+                .unwrap(),
+        );
+        let a = acme.new_window("/a.rs", "");
+        let b = acme.new_window("/b.rs", "");
+        recv(&mut s).unwrap();
+        for id in [a, b] {
+            s.cmd_put(id).unwrap();
+            recv(&mut s).unwrap();
+        }
+
+        // Without a tab size, acme's is worked out from the window's tab
+        // width in pixels and the width of 0 in its font.
+        let dir = std::env::temp_dir().join(format!("acre-font-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("font"), "1 1\n0x30 0x30 zero\n").unwrap();
+        // A one pixel high image, then a subfont whose only character is 7
+        // pixels wide.
+        let mut subfont = vec![];
+        for f in &["k1", "0", "0", "8", "1"] {
+            subfont.extend(format!("{:>11} ", f).bytes());
+        }
+        subfont.push(0);
+        for f in &[1, 1, 1] {
+            subfont.extend(format!("{:>11} ", f).bytes());
+        }
+        subfont.extend(&[0, 0, 0, 1, 0, 7, 7, 0, 0, 0, 0, 0]);
+        std::fs::write(dir.join("zero"), subfont).unwrap();
+        acme.set_font(a, dir.join("font").to_str().unwrap(), 56);
+        s.config.servers.get_mut("fake").unwrap().formatting = None;
+        s.cmd_put(a).unwrap();
+        recv(&mut s).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        fake.wait();
+        let options: Vec<Value> = fake
+            .received("textDocument/formatting")
+            .into_iter()
+            .map(|p| p["options"].clone())
+            .collect();
+        assert_eq!(
+            options,
+            vec![
+                json!({
+                    "tabSize": 2,
+                    "insertSpaces": true,
+                    "trimTrailingWhitespace": true,
+                    "insertFinalNewline": true,
+                    "trimFinalNewlines": true,
+                }),
+                json!({
+                    "tabSize": 8,
+                    "insertSpaces": true,
+                    "trimTrailingWhitespace": true,
+                    "insertFinalNewline": true,
+                    "trimFinalNewlines": false,
+                }),
+                json!({
+                    "tabSize": 8,
+                    "insertSpaces": false,
+                    "trimTrailingWhitespace": true,
+                    "insertFinalNewline": true,
+                    "trimFinalNewlines": true,
+                }),
+            ]
+        );
+
+        // Bad options are reported, and the file isn't formatted.
+        s.config.servers.get_mut("fake").unwrap().formatting =
+            Some(toml::from_str("files = { '(' = { tab_size = 8 } }\n").unwrap());
+        s.cmd_put(a).unwrap();
+        assert!(s.output.starts_with("/a.rs: regex parse error"));
+        assert!(s.requests.is_empty());
+    }
+}